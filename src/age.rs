@@ -0,0 +1,140 @@
+use crate::*;
+use serde::{Deserialize, Serialize};
+use std::fs;
+
+#[derive(Clone, Copy, Debug)]
+pub struct AgeAttributes {
+    pub sight: f32,
+    pub strength: f32,
+    pub speed: f32,
+    pub jump_cooldown: f32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AgeStage {
+    pub name: String,
+    pub duration: f64,
+    pub sight: f32,
+    pub strength: f32,
+    pub speed: f32,
+    pub jump_cooldown: f32,
+    pub collision_box_height: f32,
+    pub sprite_row: u32,
+}
+
+impl AgeStage {
+    pub fn attributes(&self) -> AgeAttributes {
+        AgeAttributes {
+            sight: self.sight,
+            strength: self.strength,
+            speed: self.speed,
+            jump_cooldown: self.jump_cooldown,
+        }
+    }
+}
+
+// Index into the life cycle stages
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Age(pub usize);
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LifeCycle {
+    pub stages: Vec<AgeStage>,
+}
+
+impl LifeCycle {
+    pub fn stage(&self, age: Age) -> &AgeStage {
+        &self.stages[age.0]
+    }
+
+    pub fn next(&self, age: Age) -> Option<Age> {
+        (age.0 + 1 < self.stages.len()).then_some(Age(age.0 + 1))
+    }
+
+    // Seconds into the life at which the given stage ends
+    pub fn time_to_change(&self, age: Age) -> f64 {
+        self.stages[..=age.0]
+            .iter()
+            .map(|stage| stage.duration)
+            .sum()
+    }
+}
+
+impl Default for LifeCycle {
+    fn default() -> Self {
+        let stage =
+            |name: &str, sight, strength, speed, jump_cooldown, height, sprite_row| AgeStage {
+                name: name.to_string(),
+                duration: 5.,
+                sight,
+                strength,
+                speed,
+                jump_cooldown,
+                collision_box_height: height,
+                sprite_row,
+            };
+
+        Self {
+            stages: vec![
+                stage("Baby", 0.5, 0.2, 0.3, 2.0, SPRITE_SIZE / 4. * 3., 0),
+                stage("Child", 0.7, 0.4, 0.6, 1.5, SPRITE_SIZE / 6. * 3.8, 1),
+                stage("Teenager", 1.0, 0.7, 0.9, 0.8, SPRITE_SIZE / 4. * 3., 2),
+                stage("Adult", 1.0, 1.0, 1.0, 0.5, SPRITE_SIZE / 6. * 5.3, 3),
+                stage("Elder", 0.6, 0.5, 0.4, 2.5, SPRITE_SIZE / 4. * 3., 4),
+            ],
+        }
+    }
+}
+
+pub fn load_life_cycle() -> LifeCycle {
+    match fs::read_to_string(AGES_PATH) {
+        Ok(content) => match serde_json::from_str::<LifeCycle>(&content) {
+            Ok(life_cycle) if life_cycle.stages.is_empty() => {
+                eprintln!("{} has no stages, using defaults", AGES_PATH);
+                LifeCycle::default()
+            }
+            Ok(life_cycle) => {
+                println!(
+                    "Loaded {} with {} stages",
+                    AGES_PATH,
+                    life_cycle.stages.len()
+                );
+                life_cycle
+            }
+            Err(e) => {
+                eprintln!("Failed to parse {}: {}", AGES_PATH, e);
+                LifeCycle::default()
+            }
+        },
+        Err(_) => {
+            println!("No {} found, using default ages.", AGES_PATH);
+            LifeCycle::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stages_change_at_the_sum_of_the_durations_so_far() {
+        let mut life_cycle = LifeCycle::default();
+        for (stage, duration) in life_cycle.stages.iter_mut().zip([1.0, 2.0, 3.0, 4.0, 5.0]) {
+            stage.duration = duration;
+        }
+
+        let changes: Vec<f64> = (0..5).map(|i| life_cycle.time_to_change(Age(i))).collect();
+        assert_eq!(changes, [1.0, 3.0, 6.0, 10.0, 15.0]);
+    }
+
+    #[test]
+    fn the_last_stage_has_no_next_one() {
+        let life_cycle = LifeCycle::default();
+        let last = Age(life_cycle.stages.len() - 1);
+
+        assert_eq!(life_cycle.next(Age(0)), Some(Age(1)));
+        assert_eq!(life_cycle.next(last), None);
+        assert_eq!(life_cycle.stage(last).name, "Elder");
+    }
+}
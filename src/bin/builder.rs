@@ -1,8 +1,325 @@
 use raylib::prelude::*;
+use retrojam::age;
+use retrojam::campaign::Campaign;
+use retrojam::history::History;
+use retrojam::script;
 use retrojam::*;
-use std::collections::HashMap;
+use std::{env, io, mem};
 
-type WorldMap = HashMap<(usize, usize), BlockType>;
+// Every key that changes the map, anything else leaves the undo history alone
+const EDIT_KEYS: [KeyboardKey; 15] = [
+    KeyboardKey::KEY_X,
+    KeyboardKey::KEY_Z,
+    KeyboardKey::KEY_P,
+    KeyboardKey::KEY_E,
+    KeyboardKey::KEY_T,
+    KeyboardKey::KEY_K,
+    KeyboardKey::KEY_M,
+    KeyboardKey::KEY_BACKSPACE,
+    KeyboardKey::KEY_N,
+    KeyboardKey::KEY_B,
+    KeyboardKey::KEY_O,
+    KeyboardKey::KEY_L,
+    KeyboardKey::KEY_G,
+    KeyboardKey::KEY_C,
+    KeyboardKey::KEY_R,
+];
+
+// Steps the real simulation on a copy of the map being edited, with its entities,
+// triggers and script, so the edits themselves are left alone
+struct PlayTest {
+    simulation: Simulation,
+    clock: Clock,
+    controls: Controls,
+}
+
+impl PlayTest {
+    fn new(
+        path: &str,
+        header: &MapHeader,
+        map: &WorldMap,
+        hovered: Option<(usize, usize)>,
+    ) -> Self {
+        let mut simulation = Simulation::new(
+            Campaign::single(path),
+            vec![(header.clone(), map.clone())],
+            age::load_life_cycle(),
+        );
+        simulation.set_scripts(vec![script::load_source(&script::script_path(path))]);
+        if let Some((x, y)) = hovered {
+            simulation.player.teleport(
+                (x as f32) * BLOCK_SIZE as f32,
+                (y as f32) * BLOCK_SIZE as f32,
+            );
+        }
+
+        Self {
+            simulation,
+            clock: Clock::default(),
+            controls: settings::load_settings().controls,
+        }
+    }
+
+    fn update(&mut self, rl: &RaylibHandle) {
+        let input = Input::read(rl, &self.controls);
+        self.clock.advance(rl.get_frame_time());
+        while self.clock.step() {
+            self.simulation.update(&input, &self.clock);
+            // Dialogue, music and sounds need the game around the level
+            self.simulation.player.age_events.clear();
+            self.simulation.trigger_events.clear();
+        }
+    }
+
+    // Follows the player at the editing zoom
+    fn camera(&self, zoom: f32) -> Camera2D {
+        let alpha = self.clock.alpha();
+        let player = &self.simulation.player;
+        let (previous_x, previous_y) = player.previous_position;
+        Camera2D {
+            offset: Vector2::new(BASE_WIDTH as f32 / 2.0, BASE_HEIGHT as f32 / 2.0),
+            target: Vector2::new(
+                smoothing(previous_x, player.body.x, alpha) + SPRITE_SIZE / 2.0,
+                smoothing(previous_y, player.body.y, alpha) + SPRITE_SIZE / 2.0,
+            ),
+            rotation: 0.0,
+            zoom,
+        }
+    }
+
+    fn status(&self) -> String {
+        let player = &self.simulation.player;
+        let mut status = format!("Play-test: {}", player.stage().name);
+        if player.exit_reached {
+            status.push_str(" | level exit reached");
+        }
+        if player.end_triggered {
+            status.push_str(" | devil reached");
+        }
+        status + " | F5/ESC: back to editing"
+    }
+}
+
+fn save(path: &str, header: &MapHeader, map: &WorldMap) {
+    if let Err(e) = save_map(path, header, map) {
+        eprintln!("Failed to save {}: {}", path, e);
+    }
+}
+
+// The edit key pressed this frame, handle_edit_input only answers to these
+fn pressed_edit_key(rl: &RaylibHandle) -> Option<KeyboardKey> {
+    EDIT_KEYS.into_iter().find(|key| rl.is_key_pressed(*key))
+}
+
+fn is_command_down(rl: &RaylibHandle) -> bool {
+    rl.is_key_down(KeyboardKey::KEY_LEFT_CONTROL)
+        || rl.is_key_down(KeyboardKey::KEY_RIGHT_CONTROL)
+        || rl.is_key_down(KeyboardKey::KEY_LEFT_SUPER)
+        || rl.is_key_down(KeyboardKey::KEY_RIGHT_SUPER)
+}
+
+fn is_shift_down(rl: &RaylibHandle) -> bool {
+    rl.is_key_down(KeyboardKey::KEY_LEFT_SHIFT) || rl.is_key_down(KeyboardKey::KEY_RIGHT_SHIFT)
+}
+
+// Text typed into the bar at the top, and what it is for
+enum PromptTarget {
+    SaveAs,
+    TriggerValue(usize), // Index into the map's triggers
+}
+
+struct Prompt {
+    target: PromptTarget,
+    input: String,
+}
+
+impl Prompt {
+    fn label(&self) -> &str {
+        match self.target {
+            PromptTarget::SaveAs => "Save as",
+            PromptTarget::TriggerValue(_) => "Trigger value",
+        }
+    }
+}
+
+// The last one placed is drawn on top, so it is the one picked
+fn trigger_at(map: &mut WorldMap, pos: (usize, usize)) -> Option<&mut Trigger> {
+    map.triggers_mut()
+        .iter_mut()
+        .rev()
+        .find(|trigger| trigger.contains(pos))
+}
+
+fn handle_edit_input(
+    rl: &RaylibHandle,
+    map: &mut WorldMap,
+    (grid_x, grid_y): (usize, usize),
+    trigger_corner: &mut Option<(usize, usize)>,
+    key: Option<KeyboardKey>,
+) {
+    let pos = (grid_x, grid_y);
+
+    if rl.is_mouse_button_pressed(MouseButton::MOUSE_BUTTON_LEFT) {
+        if map.get(pos) == Some(BlockType::Blank) {
+            map.remove(pos);
+        } else {
+            let half = DEL_SIZE / 2;
+            for dy in -half..=half {
+                for dx in -half..=half {
+                    let nx = grid_x as i32 + dx;
+                    let ny = grid_y as i32 + dy;
+                    if nx >= 0 && ny >= 0 {
+                        map.insert((nx as usize, ny as usize), BlockType::Blank);
+                    }
+                }
+            }
+        }
+        recompute_stone_borders(map);
+    }
+
+    if key == Some(KeyboardKey::KEY_X) {
+        map.retain(|_, bt| bt != BlockType::Start);
+        map.insert(pos, BlockType::Start);
+    }
+
+    if key == Some(KeyboardKey::KEY_Z) {
+        // Only one devil per level
+        map.retain_entities(|e| e.kind != EntityKind::Devil);
+        place_entity(map, EntityKind::Devil, pos);
+    }
+
+    if key == Some(KeyboardKey::KEY_P) {
+        map.insert(pos, BlockType::Slab);
+    }
+
+    if key == Some(KeyboardKey::KEY_E) {
+        map.insert(pos, BlockType::Blank);
+        map.retain_entities(|e| (e.x, e.y) != pos);
+    }
+
+    // The first press marks one corner of a trigger, the second the opposite one
+    if key == Some(KeyboardKey::KEY_T) {
+        match trigger_corner.take() {
+            Some(corner) => {
+                map.add_trigger(Trigger::between(corner, pos, TriggerAction::StopAging))
+            }
+            None => *trigger_corner = Some(pos),
+        }
+    }
+
+    if key == Some(KeyboardKey::KEY_K)
+        && let Some(trigger) = trigger_at(map, pos)
+    {
+        let presets = TriggerAction::presets();
+        let current = presets
+            .iter()
+            .position(|action| mem::discriminant(action) == mem::discriminant(&trigger.action))
+            .unwrap_or(0);
+        trigger.action = presets[(current + 1) % presets.len()].clone();
+    }
+
+    if key == Some(KeyboardKey::KEY_M)
+        && let Some(trigger) = trigger_at(map, pos)
+    {
+        trigger.mode = match trigger.mode {
+            TriggerMode::Once => TriggerMode::Repeat,
+            TriggerMode::Repeat => TriggerMode::Once,
+        };
+    }
+
+    if key == Some(KeyboardKey::KEY_BACKSPACE)
+        && let Some(index) = map.triggers().iter().rposition(|t| t.contains(pos))
+    {
+        map.remove_trigger(index);
+    }
+
+    if key == Some(KeyboardKey::KEY_N) {
+        map.insert(pos, BlockType::Exit);
+    }
+
+    if key == Some(KeyboardKey::KEY_B) {
+        map.insert(pos, BlockType::StoneSlabUp);
+    }
+
+    if key == Some(KeyboardKey::KEY_O) {
+        map.insert(pos, BlockType::Blank);
+    }
+
+    if key == Some(KeyboardKey::KEY_L) {
+        map.insert(pos, BlockType::Lever);
+    }
+
+    if key == Some(KeyboardKey::KEY_G) {
+        map.insert(pos, BlockType::Gate);
+    }
+
+    if key == Some(KeyboardKey::KEY_C) {
+        map.insert(pos, BlockType::Cracked);
+    }
+
+    if key == Some(KeyboardKey::KEY_R) {
+        place_entity(map, EntityKind::Boulder, pos);
+    }
+}
+
+// One entity per tile, a new one replaces what stood there
+fn place_entity(map: &mut WorldMap, kind: EntityKind, (x, y): (usize, usize)) {
+    map.retain_entities(|e| (e.x, e.y) != (x, y));
+    map.add_entity(EntitySpawn { kind, x, y });
+}
+
+fn update_camera(rl: &RaylibHandle, camera: &mut Camera2D) {
+    if rl.is_mouse_button_down(MouseButton::MOUSE_BUTTON_MIDDLE)
+        || rl.is_mouse_button_down(MouseButton::MOUSE_BUTTON_RIGHT)
+    {
+        camera.target -= rl.get_mouse_delta() / camera.zoom;
+    }
+
+    let step = BUILDER_PAN_SPEED / camera.zoom;
+    if rl.is_key_down(KeyboardKey::KEY_LEFT) {
+        camera.target.x -= step;
+    }
+    if rl.is_key_down(KeyboardKey::KEY_RIGHT) {
+        camera.target.x += step;
+    }
+    if rl.is_key_down(KeyboardKey::KEY_UP) {
+        camera.target.y -= step;
+    }
+    if rl.is_key_down(KeyboardKey::KEY_DOWN) {
+        camera.target.y += step;
+    }
+
+    // Zoom around the cursor so the tile under it stays put
+    let wheel = rl.get_mouse_wheel_move();
+    if wheel != 0.0 {
+        let mouse_pos = rl.get_mouse_position();
+        camera.target = rl.get_screen_to_world2D(mouse_pos, *camera);
+        camera.offset = mouse_pos;
+        camera.zoom =
+            (camera.zoom * (1.0 + 0.125 * wheel)).clamp(BUILDER_MIN_ZOOM, BUILDER_MAX_ZOOM);
+    }
+}
+
+fn resize_input(rl: &RaylibHandle, map: &WorldMap) -> Option<(usize, usize)> {
+    let (width, height) = (map.width(), map.height());
+    if rl.is_key_pressed(KeyboardKey::KEY_RIGHT) {
+        Some((width + MAP_RESIZE_STEP, height))
+    } else if rl.is_key_pressed(KeyboardKey::KEY_LEFT) {
+        Some((
+            width.saturating_sub(MAP_RESIZE_STEP).max(MAP_RESIZE_STEP),
+            height,
+        ))
+    } else if rl.is_key_pressed(KeyboardKey::KEY_DOWN) {
+        Some((width, height + MAP_RESIZE_STEP))
+    } else if rl.is_key_pressed(KeyboardKey::KEY_UP) {
+        Some((
+            width,
+            height.saturating_sub(MAP_RESIZE_STEP).max(MAP_RESIZE_STEP),
+        ))
+    } else {
+        None
+    }
+}
 
 fn main() {
     let (mut rl, thread) = raylib::init()
@@ -15,126 +332,183 @@ fn main() {
     let tileset = rl
         .load_texture(&thread, TILESET_PATH)
         .expect("Failed to load tileset");
+    let player_texture = rl
+        .load_texture(&thread, PLAYER_SPRITE_PATH)
+        .expect("Failed to load player sprite");
+
+    let mut map_path = env::args().nth(1).unwrap_or_else(|| MAP_PATH.to_string());
+    let mut prompt: Option<Prompt> = None;
+    let mut trigger_corner: Option<(usize, usize)> = None;
+    let mut play_test: Option<PlayTest> = None;
+    let mut history = History::default();
+
+    let mut camera = Camera2D {
+        offset: Vector2::zero(),
+        target: Vector2::zero(),
+        rotation: 0.0,
+        zoom: 1.0,
+    };
 
-    let mut map = load_map();
+    let (header, mut map) = match load_map(&map_path) {
+        Ok(loaded) => loaded,
+        Err(MapError::Io(e)) if e.kind() == io::ErrorKind::NotFound => {
+            println!("No {} found, starting empty.", map_path);
+            (MapHeader::default(), WorldMap::default())
+        }
+        Err(e) => {
+            eprintln!("Failed to load {}: {}", map_path, e);
+            return;
+        }
+    };
 
     while !rl.window_should_close() {
-        let mouse_pos = rl.get_mouse_position();
-        let grid_x = (mouse_pos.x as i32 / BLOCK_SIZE) as usize;
-        let grid_y = (mouse_pos.y as i32 / BLOCK_SIZE) as usize;
-
-        if rl.is_mouse_button_pressed(MouseButton::MOUSE_BUTTON_LEFT) {
-            if grid_x < GRID_WIDTH && grid_y < GRID_HEIGHT {
-                let pos = (grid_x, grid_y);
-                if map.get(&pos) == Some(&BlockType::Blank) {
-                    map.remove(&pos);
-                } else {
-                    let half = DEL_SIZE / 2;
-                    for dy in -half..=half {
-                        for dx in -half..=half {
-                            let nx = grid_x as i32 + dx;
-                            let ny = grid_y as i32 + dy;
-                            if nx >= 0
-                                && ny >= 0
-                                && nx < GRID_WIDTH as i32
-                                && ny < GRID_HEIGHT as i32
-                            {
-                                map.insert((nx as usize, ny as usize), BlockType::Blank);
-                            }
+        let mouse_pos = rl.get_screen_to_world2D(rl.get_mouse_position(), camera);
+        let (tile_x, tile_y) = WorldMap::tile_at(mouse_pos.x, mouse_pos.y);
+        let hovered = (tile_x >= 0 && tile_y >= 0)
+            .then_some((tile_x as usize, tile_y as usize))
+            .filter(|pos| map.in_bounds(*pos));
+
+        if let Some(test) = play_test.as_mut() {
+            test.update(&rl);
+
+            if rl.is_key_pressed(KeyboardKey::KEY_F5) || rl.is_key_pressed(KeyboardKey::KEY_ESCAPE)
+            {
+                play_test = None;
+                rl.set_exit_key(Some(KeyboardKey::KEY_ESCAPE));
+            }
+        } else if let Some(Prompt { target, input }) = prompt.as_mut() {
+            while let Some(c) = rl.get_char_pressed() {
+                input.push(c);
+            }
+            if rl.is_key_pressed(KeyboardKey::KEY_BACKSPACE) {
+                input.pop();
+            }
+
+            let confirmed = rl.is_key_pressed(KeyboardKey::KEY_ENTER) && !input.is_empty();
+            if confirmed {
+                match target {
+                    PromptTarget::SaveAs => {
+                        map_path = input.clone();
+                        save(&map_path, &header, &map);
+                    }
+                    PromptTarget::TriggerValue(i) => {
+                        let before = map.clone();
+                        if let Some(trigger) = map.triggers_mut().get_mut(*i) {
+                            trigger.action.set_value(input);
                         }
+                        history.record(&before, &map);
                     }
                 }
             }
-            recompute_stone_borders(&mut map);
-        }
-
-        if rl.is_key_pressed(KeyboardKey::KEY_X) {
-            if grid_x < GRID_WIDTH && grid_y < GRID_HEIGHT {
-                map.retain(|_, bt| *bt != BlockType::Start);
-                let pos = (grid_x, grid_y);
-                map.insert(pos, BlockType::Start);
+            if confirmed || rl.is_key_pressed(KeyboardKey::KEY_ESCAPE) {
+                prompt = None;
+                rl.set_exit_key(Some(KeyboardKey::KEY_ESCAPE));
             }
-        }
-
-        if rl.is_key_pressed(KeyboardKey::KEY_Z) {
-            if grid_x < GRID_WIDTH && grid_y < GRID_HEIGHT {
-                map.retain(|_, bt| *bt != BlockType::End);
-                let pos = (grid_x, grid_y);
-                map.insert(pos, BlockType::End);
+        } else if is_command_down(&rl) {
+            if rl.is_key_pressed(KeyboardKey::KEY_Y)
+                || (is_shift_down(&rl) && rl.is_key_pressed(KeyboardKey::KEY_Z))
+            {
+                history.redo(&mut map);
+            } else if rl.is_key_pressed(KeyboardKey::KEY_Z) {
+                history.undo(&mut map);
             }
-        }
-
-        if rl.is_key_pressed(KeyboardKey::KEY_P) {
-            if grid_x < GRID_WIDTH && grid_y < GRID_HEIGHT {
-                let pos = (grid_x, grid_y);
-                map.insert(pos, BlockType::Slab);
+        } else if is_shift_down(&rl) {
+            if let Some((width, height)) = resize_input(&rl, &map) {
+                let before = map.clone();
+                map.resize(width, height);
+                history.record(&before, &map);
             }
-        }
+        } else {
+            update_camera(&rl, &mut camera);
 
-        if rl.is_key_pressed(KeyboardKey::KEY_E) {
-            if grid_x < GRID_WIDTH && grid_y < GRID_HEIGHT {
-                let pos = (grid_x, grid_y);
-                map.insert(pos, BlockType::Blank);
+            let key = pressed_edit_key(&rl);
+            if let Some(pos) = hovered
+                && (key.is_some() || rl.is_mouse_button_pressed(MouseButton::MOUSE_BUTTON_LEFT))
+            {
+                let before = map.clone();
+                handle_edit_input(&rl, &mut map, pos, &mut trigger_corner, key);
+                history.record(&before, &map);
             }
-        }
 
-        if rl.is_key_pressed(KeyboardKey::KEY_T) {
-            if grid_x < GRID_WIDTH && grid_y < GRID_HEIGHT {
-                let pos = (grid_x, grid_y);
-                map.insert(pos, BlockType::StopAging);
+            if rl.is_key_pressed(KeyboardKey::KEY_S) {
+                save(&map_path, &header, &map);
             }
-        }
 
-        if rl.is_key_pressed(KeyboardKey::KEY_B) {
-            if grid_x < GRID_WIDTH && grid_y < GRID_HEIGHT {
-                let pos = (grid_x, grid_y);
-                map.insert(pos, BlockType::StoneSlabUp);
+            if rl.is_key_pressed(KeyboardKey::KEY_A) {
+                // Escape cancels the prompt instead of closing the builder
+                rl.set_exit_key(None);
+                prompt = Some(Prompt {
+                    target: PromptTarget::SaveAs,
+                    input: map_path.clone(),
+                });
             }
-        }
 
-        if rl.is_key_pressed(KeyboardKey::KEY_O) {
-            if grid_x < GRID_WIDTH && grid_y < GRID_HEIGHT {
-                let pos = (grid_x, grid_y);
-                map.insert(pos, BlockType::Blank);
+            // Only actions that take a dialogue node, track, flag or offset have a value
+            if rl.is_key_pressed(KeyboardKey::KEY_V)
+                && let Some(pos) = hovered
+                && let Some(i) = map.triggers().iter().rposition(|t| t.contains(pos))
+                && let Some(value) = map.triggers()[i].action.value()
+            {
+                rl.set_exit_key(None);
+                prompt = Some(Prompt {
+                    target: PromptTarget::TriggerValue(i),
+                    input: value,
+                });
             }
-        }
 
-        if rl.is_key_pressed(KeyboardKey::KEY_S) {
-            save_map(&map);
+            if rl.is_key_pressed(KeyboardKey::KEY_F5) {
+                // Escape ends the play-test instead of closing the builder
+                rl.set_exit_key(None);
+                play_test = Some(PlayTest::new(&map_path, &header, &map, hovered));
+            }
         }
 
         let mut d = rl.begin_drawing(&thread);
         d.clear_background(Color::DARKGRAY);
 
-        for y in 0..GRID_HEIGHT {
-            for x in 0..GRID_WIDTH {
+        {
+            let view_camera = play_test
+                .as_ref()
+                .map_or(camera, |test| test.camera(camera.zoom));
+            let mut d = d.begin_mode2D(view_camera);
+            // A play-test shows the level as the run has left it
+            let shown_map = play_test.as_ref().map_or(&map, |test| &test.simulation.map);
+
+            for (x, y) in shown_map.tiles_in(&visible_area(&view_camera), 0) {
                 let pos_x = (x as i32) * BLOCK_SIZE;
                 let pos_y = (y as i32) * BLOCK_SIZE;
 
-                match map.get(&(x, y)) {
+                match shown_map.get((x, y)) {
                     Some(block_type) => {
                         let (sprite_x, sprite_y) = block_type.to_sprite_position();
+                        // One-way platforms only show the top they can be stood on
+                        let visible = if block_type.is_one_way() { 0.25 } else { 1.0 };
 
                         let source = Rectangle::new(
                             sprite_x * SPRITE_SIZE,
                             sprite_y * SPRITE_SIZE,
                             SPRITE_SIZE,
-                            SPRITE_SIZE,
+                            SPRITE_SIZE * visible,
                         );
 
                         let dest = Rectangle::new(
                             pos_x as f32,
                             pos_y as f32,
                             BLOCK_SIZE as f32,
-                            BLOCK_SIZE as f32,
+                            BLOCK_SIZE as f32 * visible,
                         );
 
                         // Determine tint color based on block type
                         let tint_color = match block_type {
                             BlockType::Start => Color::new(100, 255, 100, 255), // Bright green
-                            BlockType::End => Color::new(255, 100, 100, 255),   // Bright red
-                            BlockType::StopAging => Color::new(100, 100, 255, 255), // Bright blue
                             BlockType::Slab => Color::new(255, 255, 100, 255),  // Yellow
+                            BlockType::Exit => Color::new(200, 100, 255, 255),  // Purple
+                            BlockType::Lever | BlockType::LeverPulled => {
+                                Color::new(255, 160, 60, 255)
+                            } // Orange
+                            BlockType::Gate => Color::new(100, 200, 200, 255),  // Teal
+                            BlockType::Cracked => Color::new(150, 130, 110, 255), // Brown
+                            BlockType::Grave => Color::new(160, 160, 160, 255), // Grey
                             _ => Color::WHITE, // Normal rendering for other blocks
                         };
 
@@ -147,8 +521,19 @@ fn main() {
                             tint_color,
                         );
 
+                        // Marks the side that can be passed through
+                        if block_type.is_one_way() {
+                            d.draw_line(
+                                pos_x + BLOCK_SIZE / 2,
+                                pos_y + BLOCK_SIZE / 2,
+                                pos_x + BLOCK_SIZE / 2,
+                                pos_y + BLOCK_SIZE,
+                                Color::new(255, 255, 100, 120),
+                            );
+                        }
+
                         // Additional highlight for Start block
-                        if *block_type == BlockType::Start {
+                        if block_type == BlockType::Start {
                             d.draw_rectangle_lines(
                                 pos_x,
                                 pos_y,
@@ -163,31 +548,131 @@ fn main() {
                     }
                 }
 
-                d.draw_rectangle_lines(
-                    pos_x,
-                    pos_y,
-                    BLOCK_SIZE,
-                    BLOCK_SIZE,
-                    Color::new(50, 50, 50, 255),
+                // Grid lines would cover the tiles when zoomed far out
+                if view_camera.zoom >= 0.5 {
+                    d.draw_rectangle_lines(
+                        pos_x,
+                        pos_y,
+                        BLOCK_SIZE,
+                        BLOCK_SIZE,
+                        Color::new(50, 50, 50, 255),
+                    );
+                }
+            }
+
+            let entities: Vec<Entity> = match &play_test {
+                Some(test) => test.simulation.entities.clone(),
+                None => map.entities().iter().map(Entity::spawn).collect(),
+            };
+            for entity in entities {
+                let color = match entity.kind {
+                    EntityKind::Devil => Color::new(255, 100, 100, 255), // Bright red
+                    EntityKind::Boulder => Color::new(255, 100, 200, 255), // Pink
+                };
+                // Outlined at full size, the devil reaches above its tile
+                let body = Rectangle::from(entity.body);
+                d.draw_rectangle_rec(body, color.alpha(0.4));
+                d.draw_rectangle_lines_ex(body, 1.0, color);
+            }
+
+            for trigger in map.triggers() {
+                let color = match trigger.mode {
+                    TriggerMode::Once => Color::new(100, 100, 255, 255), // Bright blue
+                    TriggerMode::Repeat => Color::new(100, 220, 255, 255), // Sky blue
+                };
+                let area = Rectangle::from(trigger.area());
+                d.draw_rectangle_rec(area, color.alpha(0.2));
+                d.draw_rectangle_lines_ex(area, 1.0, color);
+                d.draw_text(
+                    &format!("{:?} {:?}", trigger.action, trigger.mode),
+                    area.x as i32 + 2,
+                    area.y as i32 + 2,
+                    10,
+                    color,
                 );
             }
-        }
 
-        if grid_x < GRID_WIDTH && grid_y < GRID_HEIGHT {
-            let pos_x = (grid_x as i32) * BLOCK_SIZE;
-            let pos_y = (grid_y as i32) * BLOCK_SIZE;
-            d.draw_rectangle_lines(pos_x, pos_y, BLOCK_SIZE, BLOCK_SIZE, Color::YELLOW);
+            // The area a second T would cover
+            if let (Some(corner), Some(pos)) = (trigger_corner, hovered) {
+                let area = Trigger::between(corner, pos, TriggerAction::StopAging).area();
+                d.draw_rectangle_lines_ex(Rectangle::from(area), 1.0, Color::YELLOW);
+            }
+
+            d.draw_rectangle_lines(
+                0,
+                0,
+                map.width() as i32 * BLOCK_SIZE,
+                map.height() as i32 * BLOCK_SIZE,
+                Color::WHITE,
+            );
+
+            match &play_test {
+                Some(test) => {
+                    test.simulation
+                        .player
+                        .draw(&mut d, &player_texture, test.clock.alpha())
+                }
+                None => {
+                    if let Some((grid_x, grid_y)) = hovered {
+                        let pos_x = (grid_x as i32) * BLOCK_SIZE;
+                        let pos_y = (grid_y as i32) * BLOCK_SIZE;
+                        d.draw_rectangle_lines(pos_x, pos_y, BLOCK_SIZE, BLOCK_SIZE, Color::YELLOW);
+                    }
+                }
+            }
         }
 
         d.draw_text(
             &format!(
-                "Left Click: toggle brush ({}x{}) | P: pencil (1x1) | E: eraser (1x1) | O: single blank | B: single stone | X: set start position | Z: set devil postion | T: stop aging zone | S: to save | ESC: to leave",
+                "Left Click: toggle brush ({}x{}) | P: one-way platform | E: eraser (1x1, also boulders and devil) | O: single blank | B: single stone | X: set start position | Z: set devil postion | T: trigger corner | N: level exit",
                 DEL_SIZE, DEL_SIZE
             ),
             10,
+            BASE_HEIGHT - 32,
+            10,
+            Color::WHITE,
+        );
+        d.draw_text(
+            "L: lever (opens every gate) | G: gate | C: cracked floor (breaks once stood on) | R: boulder",
+            10,
+            BASE_HEIGHT - 44,
+            10,
+            Color::WHITE,
+        );
+        d.draw_text(
+            "T twice: trigger area | K: trigger action | M: once/repeat | V: trigger value | Backspace: delete trigger",
+            10,
+            BASE_HEIGHT - 56,
+            10,
+            Color::WHITE,
+        );
+        d.draw_text(
+            "Arrows/Right drag: pan | Wheel: zoom | Shift+Arrows: resize map | F5: play-test | S: to save | A: save as | Ctrl+Z/Y: undo/redo | ESC: to leave",
+            10,
             BASE_HEIGHT - 20,
             10,
             Color::WHITE,
         );
+
+        match (&play_test, &prompt) {
+            (Some(test), _) => d.draw_text(&test.status(), 10, 5, 10, Color::YELLOW),
+            (None, Some(prompt)) => {
+                d.draw_rectangle(0, 0, BASE_WIDTH, 20, Color::BLACK);
+                d.draw_text(
+                    &format!("{}: {}_", prompt.label(), prompt.input),
+                    10,
+                    5,
+                    10,
+                    Color::YELLOW,
+                );
+            }
+            (None, None) => d.draw_text(
+                &format!("{} ({}x{})", map_path, map.width(), map.height()),
+                10,
+                5,
+                10,
+                Color::WHITE,
+            ),
+        }
     }
 }
@@ -1,44 +1,60 @@
 use raylib::prelude::*;
+use retrojam::campaign::{self, Campaign};
+use retrojam::dialogue::{self, DialogueScript, Outcome};
+use retrojam::replay::{self, Replay, ReplayEvent};
+use retrojam::save::{self, SaveData};
+use retrojam::settings::DialogueAdvance;
 use retrojam::shaders::TORCH_FRAGMENT_SHADER;
 use retrojam::*;
+use std::env;
 use std::error::Error;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 struct AudioSystem<'a> {
+    device: &'a RaylibAudio,
     music: Music<'a>,
+    music_path: String,
     ambience: Music<'a>,
     walk_sound: Sound<'a>,
     jump_sound: Sound<'a>,
     fall_sound: Sound<'a>,
     laugh_sound: Sound<'a>,
     die_sound: Sound<'a>,
+    blip_sound: Sound<'a>,
 }
 
 impl<'a> AudioSystem<'a> {
     fn new(audio: &'a RaylibAudio) -> Result<Self, Box<dyn Error>> {
-        let music = audio.new_music("src/assets/music2.mp3")?;
+        let music = audio.new_music(MUSIC_PATH)?;
         let ambience = audio.new_music("src/assets/ambience.mp3")?;
         let walk_sound = audio.new_sound("src/assets/walk.mp3")?;
         let jump_sound = audio.new_sound("src/assets/jump.mp3")?;
         let fall_sound = audio.new_sound("src/assets/fall.mp3")?;
         let laugh_sound = audio.new_sound("src/assets/laugh.mp3")?;
         let die_sound = audio.new_sound("src/assets/die.mp3")?;
+        // The footstep sample doubles as the dialogue typing blip
+        let blip_sound = audio.new_sound("src/assets/walk.mp3")?;
 
         Sound::set_volume(&walk_sound, 0.1);
         Sound::set_volume(&jump_sound, 0.1);
         Sound::set_volume(&fall_sound, 0.02);
         Sound::set_volume(&laugh_sound, 0.2);
         Sound::set_volume(&die_sound, 0.4);
+        Sound::set_volume(&blip_sound, 0.03);
         Music::set_volume(&music, 0.8);
         Music::set_volume(&ambience, 0.15);
 
         Ok(Self {
+            device: audio,
             music,
+            music_path: MUSIC_PATH.to_string(),
             ambience,
             walk_sound,
             jump_sound,
             fall_sound,
             laugh_sound,
             die_sound,
+            blip_sound,
         })
     }
 
@@ -51,6 +67,36 @@ impl<'a> AudioSystem<'a> {
         Music::update_stream(&self.music);
         Music::update_stream(&self.ambience);
     }
+
+    // Sounds named by dialogue lines, triggers and scripts
+    fn play(&self, name: &str) {
+        match name {
+            "laugh" => Sound::play(&self.laugh_sound),
+            "blip" => Sound::play(&self.blip_sound),
+            "die" => Sound::play(&self.die_sound),
+            "jump" => Sound::play(&self.jump_sound),
+            "fall" => Sound::play(&self.fall_sound),
+            "walk" => Sound::play(&self.walk_sound),
+            _ => {}
+        }
+    }
+
+    // Keeps the current track when the new one cannot be loaded
+    fn change_music(&mut self, path: &str) {
+        if path == self.music_path {
+            return;
+        }
+        match self.device.new_music(path) {
+            Ok(music) => {
+                Music::stop_stream(&self.music);
+                Music::set_volume(&music, 0.8);
+                Music::play_stream(&music);
+                self.music = music;
+                self.music_path = path.to_string();
+            }
+            Err(e) => eprintln!("Failed to load music {}: {}", path, e),
+        }
+    }
 }
 
 struct ShaderSystem {
@@ -98,10 +144,8 @@ impl ShaderSystem {
 struct GameState {
     was_grounded: bool,
     has_laughed: bool,
-    has_played_die_sound: bool,
+    end_reached: bool, // The ending has been seen in some earlier life
     end_time: Option<f64>,
-    show_ending: bool,
-    dialogue_started: bool,
 }
 
 impl GameState {
@@ -109,67 +153,42 @@ impl GameState {
         Self {
             was_grounded: true,
             has_laughed: false,
-            has_played_die_sound: false,
+            end_reached: false,
             end_time: None,
-            show_ending: false,
-            dialogue_started: false,
         }
     }
 
-    fn handle_audio(
-        &mut self,
-        world: &World,
-        audio: &AudioSystem,
-        rl: &RaylibHandle,
-        footstep: bool,
-    ) {
-        let jump_input =
-            rl.is_key_down(KeyboardKey::KEY_UP) || rl.is_key_down(KeyboardKey::KEY_SPACE);
-        if self.was_grounded && !world.player.grounded && jump_input {
+    fn handle_audio(&mut self, world: &World, audio: &AudioSystem, input: &Input, footstep: bool) {
+        if self.was_grounded && !world.simulation.player.grounded && input.jump {
             Sound::play(&audio.jump_sound);
         }
-        if !self.was_grounded && world.player.grounded {
+        if !self.was_grounded && world.simulation.player.grounded {
             Sound::play(&audio.fall_sound);
         }
         if footstep {
             Sound::play(&audio.walk_sound);
         }
-        if !self.has_laughed && world.player.end_scene_active {
+        if !self.has_laughed && world.simulation.player.end_scene_active {
             Sound::play(&audio.laugh_sound);
             self.has_laughed = true;
         }
-        if world.player.is_dying && !self.has_played_die_sound {
+        self.was_grounded = world.simulation.player.grounded;
+    }
+
+    fn handle_age_event(&self, event: &player::AgeEvent, audio: &AudioSystem) {
+        if let player::AgeEvent::Died = event {
             Sound::play(&audio.die_sound);
-            self.has_played_die_sound = true;
         }
-        if !world.player.is_dying {
-            self.has_played_die_sound = false;
-        }
-        self.was_grounded = world.player.grounded;
     }
 
-    fn update_ending(&mut self, current_time: f64, world: &World) {
-        if world.player.end_triggered && self.end_time.is_none() {
+    // True once the end has been standing long enough for the ending to take over
+    fn ending_reached(&mut self, current_time: f64, world: &World) -> bool {
+        if world.simulation.player.end_triggered && self.end_time.is_none() {
             self.end_time = Some(current_time);
         }
 
-        if let Some(end_time) = self.end_time {
-            if current_time - end_time >= 6.0 {
-                self.show_ending = true;
-            }
-        }
-    }
-
-    fn should_show_ending(&self) -> bool {
-        self.show_ending
-    }
-
-    fn should_start_dialogue(&mut self) -> bool {
-        if self.show_ending && !self.dialogue_started {
-            self.dialogue_started = true;
-            return true;
-        }
-        false
+        self.end_time
+            .is_some_and(|end_time| current_time - end_time >= 6.0)
     }
 }
 
@@ -238,181 +257,727 @@ impl RenderTarget {
             Color::WHITE,
         );
     }
+
+    pub fn draw_overlay(
+        &mut self,
+        d: &mut RaylibDrawHandle,
+        offset_x: f32,
+        offset_y: f32,
+        scaled_width: f32,
+        scaled_height: f32,
+    ) {
+        d.draw_texture_pro(
+            self.texture.texture(),
+            Rectangle {
+                x: 0.0,
+                y: 0.0,
+                width: BASE_WIDTH as f32,
+                height: -BASE_HEIGHT as f32,
+            },
+            Rectangle {
+                x: offset_x,
+                y: offset_y,
+                width: scaled_width,
+                height: scaled_height,
+            },
+            Vector2::zero(),
+            0.0,
+            Color::WHITE,
+        );
+    }
 }
 
-fn calculate_fade_alpha(world: &World, rl: &RaylibHandle) -> u8 {
-    if !world.player.is_dying {
+fn calculate_fade_alpha(world: &World, time: f64) -> u8 {
+    if !world.simulation.player.is_dying {
         return 0;
     }
-    let elapsed = rl.get_time() - world.player.death_start_time;
+    let elapsed = time - world.simulation.player.death_start_time;
     let progress = (elapsed / DEATH_ANIMATION_DURATION).clamp(0.0, 1.0);
     let eased = progress * progress * (3.0 - 2.0 * progress);
     (eased * 255.0) as u8
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let (mut rl, thread) = raylib::init()
-        .size(BASE_WIDTH, BASE_HEIGHT)
-        .title("RetroJam 2025")
-        .resizable()
-        .build();
-    rl.set_target_fps(TARGET_FPS);
+const CREDITS: [&str; 6] = [
+    "Sisyphus",
+    "",
+    "A game made for IEEE RetroJam 2025",
+    "by Los Pollos Hermanos",
+    "",
+    "Thank you for playing",
+];
+
+// Continuing, picking a level and the credits are offered once the save has got that far
+fn title_menu() -> Menu {
+    let save = save::load_save();
+    let mut items = Vec::new();
+    if let Some(save) = &save {
+        items.push("Continue");
+        if save.unlocked_levels > 1 {
+            items.push("Select Level");
+        }
+        if save.end_reached {
+            items.push("Credits");
+        }
+    }
+    items.extend(["New Game", "Settings", "Quit"]);
+    Menu::new("Sisyphus", &items)
+}
 
-    let audio_device = RaylibAudio::init_audio_device()?;
-    let audio = AudioSystem::new(&audio_device)?;
-    audio.start();
+fn level_menu(campaign: &Campaign, unlocked_levels: usize) -> Menu {
+    let mut items: Vec<&str> = campaign
+        .levels
+        .iter()
+        .take(unlocked_levels)
+        .map(|level| level.name.as_str())
+        .collect();
+    items.push("Back");
+    Menu::new("Select Level", &items)
+}
 
-    let mut shader_system = ShaderSystem::new(&mut rl, &thread);
-    let mut render_target = RenderTarget::new(&mut rl, &thread)?;
-    let mut world = World::new(&mut rl, &thread)?;
-    let mut game_state = GameState::new();
+fn pause_menu() -> Menu {
+    Menu::new(
+        "Paused",
+        &["Resume", "Restart Life", "Settings", "Quit to Title"],
+    )
+}
 
-    world.dust.spawn(&mut rl, &world.camera);
+struct SettingsMenu {
+    menu: Menu,
+    rebinding: Option<Action>, // Waiting for a key or button for this action
+}
 
-    let mut step_counter = 0;
+impl SettingsMenu {
+    fn new(settings: &Settings) -> Self {
+        let mut menu = Self {
+            menu: Menu::new("Settings", &[]),
+            rebinding: None,
+        };
+        menu.refresh(settings);
+        menu
+    }
 
-    let mut dialogue = DialogueSystem::new(&mut rl, &thread)?;
+    // One row per action, then the dialogue mode and Back
+    fn refresh(&mut self, settings: &Settings) {
+        let mut items: Vec<String> = Action::ALL
+            .iter()
+            .map(|action| match self.rebinding {
+                Some(rebinding) if rebinding == *action => format!("{:?}: press a key", action),
+                _ => format!("{:?}: {}", action, settings.controls.label(*action)),
+            })
+            .collect();
+        items.push(format!("Dialogue: {:?}", settings.dialogue_advance));
+        items.push("Back".to_string());
+        self.menu.items = items;
+    }
+}
 
-    while !rl.window_should_close() {
-        audio.update();
+// Everything the scenes share
+struct Game<'a> {
+    world: World,
+    game_state: GameState,
+    audio: AudioSystem<'a>,
+    settings: Settings,
+    dialogue: DialogueScript,
+    font: Font,
+    clock: Clock,
+    shader_system: ShaderSystem,
+    world_target: RenderTarget,
+    ui_target: RenderTarget,
+    playback: Option<Replay>,
+    recording: Option<Replay>,
+    step_counter: i32,
+}
 
-        let delta_time = rl.get_frame_time();
-        let time = rl.get_time();
+impl Game<'_> {
+    fn begin_run(&mut self, rl: &mut RaylibHandle, seed: u32) {
+        rl.set_random_seed(seed);
+        self.world.dust.spawn(rl, &self.world.camera);
+        self.game_state = GameState::new();
+        self.clock = Clock::default();
+        self.step_counter = 0;
+    }
 
-        // Update ending state
-        game_state.update_ending(time, &world);
+    // Fresh run on the same campaign, or picked up from a save, recorded from its first step
+    fn new_run(&mut self, rl: &mut RaylibHandle, save: Option<SaveData>) {
+        self.save_recording();
 
-        // Start dialogue when ending begins
-        if game_state.should_start_dialogue() {
-            dialogue.start(time);
+        let mut simulation = Simulation::new(
+            self.world.simulation.campaign.clone(),
+            self.world.simulation.levels.clone(),
+            age::load_life_cycle(),
+        );
+        simulation.set_scripts(self.world.simulation.scripts.clone());
+        match &save {
+            Some(save) => save.restore(&mut simulation),
+            None => {
+                if let Some(previous) = save::load_save() {
+                    previous.keep_unlocked(&mut simulation);
+                }
+            }
+        }
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs() as u32);
+
+        self.playback = None;
+        self.recording = Some(Replay::new(seed, &simulation));
+        self.audio.change_music(MUSIC_PATH);
+        self.world.reset(simulation);
+        self.begin_run(rl, seed);
+
+        if let Some(save) = save {
+            self.game_state.has_laughed = save.has_laughed;
+            self.game_state.end_reached = save.end_reached;
         }
+    }
 
-        // Update dialogue and play sound effects
-        if let Some(sound_name) = dialogue.update(time) {
-            match sound_name.as_str() {
-                "laugh" => Sound::play(&audio.laugh_sound),
-                "drip" | _ => {}
+    // Changes the run between steps and records it so the replay makes the change at the same step,
+    // while playing back only the replay's own events change the run
+    fn change_run(&mut self, event: ReplayEvent) {
+        if self.playback.is_some() {
+            return;
+        }
+        if let Some(replay) = self.recording.as_mut() {
+            replay.record_event(self.clock.time, &event);
+        }
+        self.apply_event(&event);
+    }
+
+    fn apply_event(&mut self, event: &ReplayEvent) {
+        event.apply(&mut self.world.simulation);
+        match event {
+            ReplayEvent::RestartLife => {
+                let laughed = self.game_state.has_laughed;
+                let end_reached = self.game_state.end_reached;
+
+                self.game_state = GameState::new();
+                self.game_state.has_laughed = laughed;
+                self.game_state.end_reached = end_reached;
             }
+            ReplayEvent::NextLevel => self.world.snap_camera(),
+            _ => {}
+        }
+    }
+
+    fn play_back_events(&mut self) {
+        let events = self
+            .playback
+            .as_mut()
+            .map(Replay::take_events)
+            .unwrap_or_default();
+        for (time, event) in events {
+            self.clock.time = time;
+            self.apply_event(&event);
         }
+    }
+
+    // Replays never overwrite the player's own progress
+    fn autosave(&self) {
+        if self.playback.is_some() {
+            return;
+        }
+        let save = SaveData::new(
+            &self.world.simulation,
+            self.game_state.end_reached,
+            self.game_state.has_laughed,
+        );
+        if let Err(e) = save::save_game(&save) {
+            eprintln!("Failed to save {}: {}", SAVE_PATH, e);
+        }
+    }
 
-        // Handle dialogue choice
-        if let Some(choice) = dialogue.handle_choice(&rl) {
-            if choice {
-                // Live - respawn player
-                world.player.respawn();
+    fn save_recording(&self) {
+        if let Some(replay) = &self.recording
+            && let Err(e) = replay.save(REPLAY_PATH)
+        {
+            eprintln!("Failed to save {}: {}", REPLAY_PATH, e);
+        }
+    }
 
-                let laughed = game_state.has_laughed;
+    fn save_settings(&self) {
+        if let Err(e) = settings::save_settings(&self.settings) {
+            eprintln!("Failed to save {}: {}", SETTINGS_PATH, e);
+        }
+    }
 
-                game_state = GameState::new();
-                game_state.has_laughed = laughed;
+    fn draw(
+        &mut self,
+        rl: &mut RaylibHandle,
+        thread: &RaylibThread,
+        scenes: &mut [Scene],
+    ) -> Result<(), Box<dyn Error>> {
+        let screen_width = rl.get_screen_width() as f32;
+        let screen_height = rl.get_screen_height() as f32;
 
-                dialogue = DialogueSystem::new(&mut rl, &thread)?;
-            } else {
-                // Die - close program
-                break;
+        // The world stays visible under the pause and settings menus
+        let world_visible = matches!(scenes.first(), Some(Scene::Gameplay));
+        let time = self.clock.time;
+
+        if world_visible {
+            let sight = self.world.simulation.player.get_sight_multiplier(time);
+            self.shader_system.update_light(sight);
+
+            self.world_target.check_resize(rl, thread)?;
+            let mut texture_mode = rl.begin_texture_mode(thread, self.world_target.get_mut());
+            texture_mode.clear_background(Color::BLACK);
+            self.world.draw(
+                &mut texture_mode,
+                &(screen_width as i32),
+                &(screen_height as i32),
+                self.clock.alpha(),
+            );
+        }
+
+        self.ui_target.check_resize(rl, thread)?;
+        {
+            let mut texture_mode = rl.begin_texture_mode(thread, self.ui_target.get_mut());
+            texture_mode.clear_background(Color::BLANK);
+            if let Some(scene) = scenes.last_mut() {
+                scene.draw(&mut texture_mode, &self.font);
+            }
+        }
+
+        let fade_alpha = calculate_fade_alpha(&self.world, time);
+
+        let scale = calculate_scale(screen_width, screen_height);
+        let (scaled_width, scaled_height) = calculate_scaled_dimensions(scale);
+        let (offset_x, offset_y) =
+            calculate_offsets(screen_width, screen_height, scaled_width, scaled_height);
+
+        let mut d = rl.begin_drawing(thread);
+        d.clear_background(Color::BLACK);
+
+        if world_visible {
+            self.world_target.draw_to_screen(
+                &mut d,
+                self.shader_system.get_shader_mut(),
+                offset_x,
+                offset_y,
+                scaled_width,
+                scaled_height,
+            );
+
+            if fade_alpha > 0 {
+                d.draw_rectangle(
+                    0,
+                    0,
+                    d.get_screen_width(),
+                    d.get_screen_height(),
+                    Color::new(0, 0, 0, fade_alpha),
+                );
+            }
+        }
+
+        self.ui_target
+            .draw_overlay(&mut d, offset_x, offset_y, scaled_width, scaled_height);
+
+        Ok(())
+    }
+}
+
+enum Transition {
+    None,
+    Push(Scene),
+    Pop,
+    Replace(Scene),
+    Reset(Scene), // Replaces the whole stack
+    Quit,
+}
+
+// Scenes stack up, the top one is updated and anything it covers stays paused
+enum Scene {
+    Title(Menu),
+    LevelSelect(Menu), // Over the title, the levels the save has reached
+    Gameplay,
+    Pause(Menu),
+    Settings(SettingsMenu),
+    Ending(Box<DialogueSystem>),
+    Dialogue(Box<DialogueSystem>), // Started by a trigger, played over the level
+    Credits(f32),                  // Seconds since the credits started
+}
+
+impl Scene {
+    fn update(&mut self, game: &mut Game, rl: &mut RaylibHandle) -> Transition {
+        match self {
+            Scene::Title(menu) => {
+                let picked = menu.handle_input(rl, &game.settings.controls);
+                match picked.map(|i| menu.items[i].as_str()) {
+                    Some("Continue") => {
+                        game.new_run(rl, save::load_save());
+                        Transition::Replace(Scene::Gameplay)
+                    }
+                    Some("Select Level") => {
+                        let unlocked = save::load_save().map_or(1, |save| save.unlocked_levels);
+                        let campaign = &game.world.simulation.campaign;
+                        Transition::Push(Scene::LevelSelect(level_menu(campaign, unlocked)))
+                    }
+                    Some("Credits") => Transition::Replace(Scene::Credits(0.0)),
+                    Some("New Game") => {
+                        game.new_run(rl, None);
+                        Transition::Replace(Scene::Gameplay)
+                    }
+                    Some("Settings") => {
+                        Transition::Push(Scene::Settings(SettingsMenu::new(&game.settings)))
+                    }
+                    Some(_) => Transition::Quit,
+                    None => Transition::None,
+                }
+            }
+            Scene::LevelSelect(menu) => {
+                if game.settings.controls.is_pressed(rl, Action::Pause) {
+                    return Transition::Pop;
+                }
+                match menu.handle_input(rl, &game.settings.controls) {
+                    Some(i) if i + 1 < menu.items.len() => {
+                        game.new_run(rl, save::load_save().map(|save| save.at_level(i)));
+                        Transition::Reset(Scene::Gameplay)
+                    }
+                    Some(_) => Transition::Pop,
+                    None => Transition::None,
+                }
+            }
+            Scene::Gameplay => Self::update_gameplay(game, rl),
+            Scene::Pause(menu) => {
+                if game.settings.controls.is_pressed(rl, Action::Pause) {
+                    return Transition::Pop;
+                }
+                match menu.handle_input(rl, &game.settings.controls) {
+                    Some(0) => Transition::Pop,
+                    Some(1) => {
+                        game.change_run(ReplayEvent::RestartLife);
+                        Transition::Pop
+                    }
+                    Some(2) => Transition::Push(Scene::Settings(SettingsMenu::new(&game.settings))),
+                    Some(_) => Transition::Reset(Scene::Title(title_menu())),
+                    None => Transition::None,
+                }
+            }
+            Scene::Settings(menu) => Self::update_settings(menu, game, rl),
+            Scene::Ending(dialogue) => Self::update_dialogue(dialogue, game, rl, true),
+            Scene::Dialogue(dialogue) => Self::update_dialogue(dialogue, game, rl, false),
+            Scene::Credits(elapsed) => {
+                *elapsed += rl.get_frame_time();
+                let length = BASE_HEIGHT as f32 + CREDITS.len() as f32 * CREDITS_LINE_HEIGHT;
+                let skipped = game.settings.controls.is_pressed(rl, Action::Confirm)
+                    || game.settings.controls.is_pressed(rl, Action::Pause);
+                if skipped || *elapsed * CREDITS_SCROLL_SPEED > length {
+                    Transition::Reset(Scene::Title(title_menu()))
+                } else {
+                    Transition::None
+                }
             }
         }
+    }
+
+    fn update_gameplay(game: &mut Game, rl: &mut RaylibHandle) -> Transition {
+        if game.settings.controls.is_pressed(rl, Action::Pause) {
+            return Transition::Push(Scene::Pause(pause_menu()));
+        }
+
+        // A restart recorded at the end of the ending has to come before it is checked again
+        game.play_back_events();
+
+        // Start the dialogue once the ending has played out
+        if game.game_state.ending_reached(game.clock.time, &game.world) {
+            game.game_state.end_reached = true;
+            game.autosave();
+            let mut dialogue = DialogueSystem::new(&game.dialogue, &game.settings);
+            dialogue.start(game.clock.time);
+            return Transition::Replace(Scene::Ending(Box::new(dialogue)));
+        }
+
+        let input = Input::read(rl, &game.settings.controls);
+        game.clock.advance(rl.get_frame_time());
+
+        let mut dialogue_node = None;
+        while game.clock.step() {
+            game.play_back_events();
+            let input = match game.playback.as_mut() {
+                Some(replay) => replay.next_input().unwrap_or_default(),
+                None => input,
+            };
+            if let Some(replay) = game.recording.as_mut() {
+                replay.record(&input);
+            }
 
-        // Only update game logic if not showing ending
-        if !game_state.should_show_ending() {
-            let footstep = world.player.after_move(&mut rl, &mut world.map);
+            let footstep = game.world.update(&input, &game.clock);
             if footstep {
-                step_counter += 1;
+                game.step_counter += 1;
+            }
+            let should_play_footstep = footstep && game.step_counter % 2 == 0;
+
+            game.game_state
+                .handle_audio(&game.world, &game.audio, &input, should_play_footstep);
+            let mut reborn = false;
+            for event in game.world.simulation.player.age_events.drain(..) {
+                reborn |= matches!(event, player::AgeEvent::Reborn);
+                game.game_state.handle_age_event(&event, &game.audio);
+            }
+            if reborn {
+                game.autosave();
             }
-            let should_play_footstep = footstep && step_counter % 2 == 0;
 
-            game_state.handle_audio(&world, &audio, &rl, should_play_footstep);
+            for action in game.world.simulation.trigger_events.drain(..) {
+                match action {
+                    TriggerAction::ChangeMusic(path) => game.audio.change_music(&path),
+                    TriggerAction::PlaySound(name) => game.audio.play(&name),
+                    TriggerAction::StartDialogue(node) => dialogue_node = Some(node),
+                    _ => {}
+                }
+            }
 
-            world.player.update_sight(delta_time);
-            let sight = world.player.get_sight_multiplier(&rl);
-            shader_system.update_light(sight);
+            game.world.update_cam();
+            game.world.dust.update(rl);
 
-            world.update_cam();
-            world.dust.update(&mut rl);
+            if dialogue_node.is_some() {
+                break;
+            }
         }
 
-        let screen_width = rl.get_screen_width() as f32;
-        let screen_height = rl.get_screen_height() as f32;
+        if let Some(node) = dialogue_node {
+            let mut dialogue = DialogueSystem::new(&game.dialogue, &game.settings);
+            dialogue.start_at(&node, rl.get_time());
+            return Transition::Push(Scene::Dialogue(Box::new(dialogue)));
+        }
 
-        let fade_alpha = calculate_fade_alpha(&world, &rl);
-
-        if !game_state.should_show_ending() {
-            render_target.check_resize(&mut rl, &thread)?;
-            {
-                let mut texture_mode = rl.begin_texture_mode(&thread, render_target.get_mut());
-                texture_mode.clear_background(Color::BLACK);
-                world.draw(
-                    &mut texture_mode,
-                    &(screen_width as i32),
-                    &(screen_height as i32),
-                    &time,
-                );
+        Transition::None
+    }
+
+    fn update_settings(
+        menu: &mut SettingsMenu,
+        game: &mut Game,
+        rl: &mut RaylibHandle,
+    ) -> Transition {
+        if let Some(action) = menu.rebinding {
+            // Escape gives up on rebinding instead of being bound
+            let bound = match rl.get_key_pressed() {
+                Some(KeyboardKey::KEY_ESCAPE) => {
+                    menu.rebinding = None;
+                    false
+                }
+                Some(key) => game.settings.controls.bind_key(action, key),
+                None => rl.get_gamepad_button_pressed().is_some_and(|button| {
+                    rl.is_gamepad_button_pressed(GAMEPAD, button)
+                        && game.settings.controls.bind_button(action, button)
+                }),
+            };
+            if bound {
+                menu.rebinding = None;
+                game.save_settings();
             }
+            menu.refresh(&game.settings);
+            return Transition::None;
         }
 
-        render_target.check_resize(&mut rl, &thread)?;
+        if game.settings.controls.is_pressed(rl, Action::Pause) {
+            return Transition::Pop;
+        }
 
-        if game_state.should_show_ending() {
-            let mut texture_mode = rl.begin_texture_mode(&thread, render_target.get_mut());
-            texture_mode.clear_background(Color::BLACK);
-            dialogue.draw(&mut texture_mode, BASE_WIDTH, BASE_HEIGHT);
+        let actions = Action::ALL.len();
+        match menu.menu.handle_input(rl, &game.settings.controls) {
+            Some(i) if i < actions => {
+                menu.rebinding = Some(Action::ALL[i]);
+                menu.refresh(&game.settings);
+                Transition::None
+            }
+            Some(i) if i == actions => {
+                game.settings.dialogue_advance = match game.settings.dialogue_advance {
+                    DialogueAdvance::Auto => DialogueAdvance::Manual,
+                    DialogueAdvance::Manual => DialogueAdvance::Auto,
+                };
+                game.save_settings();
+                menu.refresh(&game.settings);
+                Transition::None
+            }
+            Some(_) => Transition::Pop,
+            None => Transition::None,
         }
+    }
 
-        {
-            let mut d = rl.begin_drawing(&thread);
-            d.clear_background(Color::BLACK);
-
-            if game_state.should_show_ending() {
-                let scale = calculate_scale(screen_width, screen_height);
-                let (scaled_width, scaled_height) = calculate_scaled_dimensions(scale);
-                let (offset_x, offset_y) =
-                    calculate_offsets(screen_width, screen_height, scaled_width, scaled_height);
-
-                d.draw_texture_pro(
-                    render_target.get_mut().texture(),
-                    Rectangle {
-                        x: 0.0,
-                        y: 0.0,
-                        width: BASE_WIDTH as f32,
-                        height: -BASE_HEIGHT as f32,
-                    },
-                    Rectangle {
-                        x: offset_x,
-                        y: offset_y,
-                        width: scaled_width,
-                        height: scaled_height,
-                    },
-                    Vector2::zero(),
-                    0.0,
-                    Color::WHITE,
-                );
+    fn update_dialogue(
+        dialogue: &mut DialogueSystem,
+        game: &mut Game,
+        rl: &RaylibHandle,
+        ending: bool,
+    ) -> Transition {
+        // The clock keeps running for the ending while the world stands still,
+        // a trigger's dialogue leaves it alone so the life carries on where it was
+        let time = if ending {
+            game.clock.advance(rl.get_frame_time());
+            while game.clock.step() {}
+            game.clock.time
+        } else {
+            rl.get_time()
+        };
+
+        // Update dialogue and play sound effects
+        if let Some(sound_name) = dialogue.update(time, &game.world.simulation) {
+            game.audio.play(&sound_name);
+        }
+
+        // Handle dialogue skipping and choices, then apply what they led to
+        dialogue.handle_input(rl, &game.settings.controls, time);
+
+        let mut new_life = false;
+        let mut game_over = false;
+        for outcome in dialogue.take_outcomes() {
+            match outcome {
+                Outcome::SetFlag(flag) => game.change_run(ReplayEvent::SetFlag(flag)),
+                Outcome::ClearFlag(flag) => game.change_run(ReplayEvent::ClearFlag(flag)),
+                Outcome::Respawn => new_life = true,
+                Outcome::NextLevel => {
+                    game.change_run(ReplayEvent::NextLevel);
+                    new_life = true;
+                }
+                Outcome::GameOver => game_over = true,
+            }
+        }
+
+        if game_over {
+            return Transition::Reset(Scene::Credits(0.0));
+        }
+
+        // An ending that runs out without an outcome goes back to playing
+        if new_life || (ending && dialogue.is_finished()) {
+            game.change_run(ReplayEvent::RestartLife);
+            return if ending {
+                Transition::Replace(Scene::Gameplay)
             } else {
-                let scale = calculate_scale(screen_width, screen_height);
-                let (scaled_width, scaled_height) = calculate_scaled_dimensions(scale);
-                let (offset_x, offset_y) =
-                    calculate_offsets(screen_width, screen_height, scaled_width, scaled_height);
-
-                render_target.draw_to_screen(
-                    &mut d,
-                    shader_system.get_shader_mut(),
-                    offset_x,
-                    offset_y,
-                    scaled_width,
-                    scaled_height,
-                );
+                Transition::Pop
+            };
+        }
+
+        if dialogue.is_finished() {
+            Transition::Pop
+        } else {
+            Transition::None
+        }
+    }
 
-                if fade_alpha > 0 {
-                    d.draw_rectangle(
-                        0,
-                        0,
-                        d.get_screen_width(),
-                        d.get_screen_height(),
-                        Color::new(0, 0, 0, fade_alpha),
-                    );
+    fn draw<D: RaylibDraw>(&mut self, d: &mut D, font: &Font) {
+        match self {
+            Scene::Title(menu) | Scene::LevelSelect(menu) => {
+                menu.draw(d, font, BASE_WIDTH, BASE_HEIGHT)
+            }
+            Scene::Gameplay => {}
+            Scene::Pause(menu) => {
+                d.draw_rectangle(0, 0, BASE_WIDTH, BASE_HEIGHT, Color::new(0, 0, 0, 160));
+                menu.draw(d, font, BASE_WIDTH, BASE_HEIGHT);
+            }
+            Scene::Settings(menu) => {
+                d.draw_rectangle(0, 0, BASE_WIDTH, BASE_HEIGHT, Color::new(0, 0, 0, 200));
+                menu.menu.draw(d, font, BASE_WIDTH, BASE_HEIGHT);
+            }
+            Scene::Ending(dialogue) | Scene::Dialogue(dialogue) => {
+                dialogue.draw(d, font, BASE_WIDTH, BASE_HEIGHT)
+            }
+            Scene::Credits(elapsed) => {
+                // Roll up from the bottom of the screen
+                let top = BASE_HEIGHT as f32 - *elapsed * CREDITS_SCROLL_SPEED;
+                for (i, line) in CREDITS.iter().enumerate() {
+                    let y_pos = top + i as f32 * CREDITS_LINE_HEIGHT;
+                    let (size, color) = if i == 0 {
+                        (CREDITS_LINE_HEIGHT, Color::new(251, 73, 52, 255))
+                    } else {
+                        (CREDITS_LINE_HEIGHT * 0.6, Color::new(235, 219, 178, 255))
+                    };
+                    menu::draw_centered(d, font, line, y_pos, size, color, BASE_WIDTH);
                 }
             }
         }
     }
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let (mut rl, thread) = raylib::init()
+        .size(BASE_WIDTH, BASE_HEIGHT)
+        .title("RetroJam 2025")
+        .resizable()
+        .build();
+    rl.set_target_fps(TARGET_FPS);
+    // Escape opens the pause menu instead of closing the window
+    rl.set_exit_key(None);
+
+    let audio_device = RaylibAudio::init_audio_device()?;
+    let audio = AudioSystem::new(&audio_device)?;
+    audio.start();
+
+    // --replay plays a recorded run back, any other argument is a map to play on its own
+    let args: Vec<String> = env::args().skip(1).collect();
+    let playback = match args.first().map(String::as_str) {
+        Some("--replay") => Some(replay::load_replay(
+            args.get(1).map_or(REPLAY_PATH, String::as_str),
+        )?),
+        _ => None,
+    };
+    let simulation = match &playback {
+        Some(replay) => replay.simulation()?,
+        None => {
+            let campaign = match args.first() {
+                Some(path) => Campaign::single(path),
+                None => campaign::load_campaign(),
+            };
+            let levels = campaign.load_maps()?;
+            let scripts = campaign.load_scripts();
+            let mut simulation = Simulation::new(campaign, levels, age::load_life_cycle());
+            simulation.set_scripts(scripts);
+            simulation
+        }
+    };
+
+    let mut game = Game {
+        world: World::new(&mut rl, &thread, simulation)?,
+        game_state: GameState::new(),
+        audio,
+        settings: settings::load_settings(),
+        dialogue: dialogue::load_script(),
+        font: rl.load_font(&thread, FONT_PATH)?,
+        clock: Clock::default(),
+        shader_system: ShaderSystem::new(&mut rl, &thread),
+        world_target: RenderTarget::new(&mut rl, &thread)?,
+        ui_target: RenderTarget::new(&mut rl, &thread)?,
+        playback: None,
+        recording: None,
+        step_counter: 0,
+    };
+
+    // Replays skip the title and start playing straight away
+    let mut scenes = match playback {
+        Some(replay) => {
+            game.begin_run(&mut rl, replay.seed);
+            game.playback = Some(replay);
+            vec![Scene::Gameplay]
+        }
+        None => vec![Scene::Title(title_menu())],
+    };
+
+    while !rl.window_should_close() {
+        game.audio.update();
+
+        let Some(mut scene) = scenes.pop() else {
+            break;
+        };
+        let transition = scene.update(&mut game, &mut rl);
+        scenes.push(scene);
+
+        match transition {
+            Transition::None => {}
+            Transition::Push(next) => scenes.push(next),
+            Transition::Pop => {
+                scenes.pop();
+            }
+            Transition::Replace(next) => {
+                scenes.pop();
+                scenes.push(next);
+            }
+            Transition::Reset(next) => scenes = vec![next],
+            Transition::Quit => break,
+        }
+
+        game.draw(&mut rl, &thread, &mut scenes)?;
+    }
+
+    game.save_recording();
 
     Ok(())
 }
@@ -0,0 +1,48 @@
+use retrojam::replay;
+use retrojam::*;
+use std::env;
+use std::error::Error;
+
+// Steps a recorded run without opening a window and reports what happened
+fn main() -> Result<(), Box<dyn Error>> {
+    let path = env::args()
+        .nth(1)
+        .unwrap_or_else(|| REPLAY_PATH.to_string());
+    let mut replay = replay::load_replay(&path)?;
+    let mut simulation = replay.simulation()?;
+    let mut clock = Clock::default();
+
+    loop {
+        for (time, event) in replay.take_events() {
+            clock.time = time;
+            println!("[{:.2}s] {:?}", clock.time, event);
+            event.apply(&mut simulation);
+        }
+        let Some(input) = replay.next_input() else {
+            break;
+        };
+
+        clock.tick(FIXED_TIMESTEP as f32);
+        simulation.update(&input, &clock);
+
+        for event in simulation.player.age_events.drain(..) {
+            println!("[{:.2}s] {:?}", clock.time, event);
+        }
+        for action in simulation.trigger_events.drain(..) {
+            println!("[{:.2}s] {:?}", clock.time, action);
+        }
+    }
+
+    let player = &simulation.player;
+    println!(
+        "Finished after {} steps ({:.2}s) on level {} at ({:.1}, {:.1}), age {}{}",
+        replay.steps(),
+        clock.time,
+        simulation.current_level().name,
+        player.body.x,
+        player.body.y,
+        player.stage().name,
+        if player.is_dying { ", dying" } else { "" }
+    );
+    Ok(())
+}
@@ -0,0 +1,83 @@
+use crate::*;
+use serde::{Deserialize, Serialize};
+use std::fs;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum LevelEnding {
+    #[default]
+    Devil, // Reaching the devil starts the devil scene
+    NextLevel, // Reaching the devil moves on to the next level
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Level {
+    pub name: String,
+    pub map: String,
+    #[serde(default)]
+    pub ending: LevelEnding,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Campaign {
+    pub levels: Vec<Level>,
+}
+
+impl Campaign {
+    pub fn single(map: &str) -> Self {
+        Self {
+            levels: vec![Level {
+                name: "Sisyphus".to_string(),
+                map: map.to_string(),
+                ending: LevelEnding::Devil,
+            }],
+        }
+    }
+
+    // Every level is loaded up front so a broken map fails at startup, not mid-run
+    pub fn load_maps(&self) -> Result<Vec<(MapHeader, WorldMap)>, MapError> {
+        self.levels
+            .iter()
+            .map(|level| load_map(&level.map))
+            .collect()
+    }
+
+    pub fn load_scripts(&self) -> Vec<Option<String>> {
+        self.levels
+            .iter()
+            .map(|level| script::load_source(&script::script_path(&level.map)))
+            .collect()
+    }
+}
+
+impl Default for Campaign {
+    fn default() -> Self {
+        Self::single(MAP_PATH)
+    }
+}
+
+pub fn load_campaign() -> Campaign {
+    match fs::read_to_string(CAMPAIGN_PATH) {
+        Ok(content) => match serde_json::from_str::<Campaign>(&content) {
+            Ok(campaign) if campaign.levels.is_empty() => {
+                eprintln!("{} has no levels, using defaults", CAMPAIGN_PATH);
+                Campaign::default()
+            }
+            Ok(campaign) => {
+                println!(
+                    "Loaded {} with {} levels",
+                    CAMPAIGN_PATH,
+                    campaign.levels.len()
+                );
+                campaign
+            }
+            Err(e) => {
+                eprintln!("Failed to parse {}: {}", CAMPAIGN_PATH, e);
+                Campaign::default()
+            }
+        },
+        Err(_) => {
+            println!("No {} found, playing {} only.", CAMPAIGN_PATH, MAP_PATH);
+            Campaign::default()
+        }
+    }
+}
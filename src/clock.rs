@@ -0,0 +1,41 @@
+use crate::*;
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Clock {
+    pub time: f64,
+    pub delta: f32,
+    accumulator: f64,
+}
+
+impl Clock {
+    pub fn new(time: f64, delta: f32) -> Self {
+        Self {
+            time,
+            delta,
+            accumulator: 0.0,
+        }
+    }
+
+    pub fn tick(&mut self, delta: f32) {
+        self.time += delta as f64;
+        self.delta = delta;
+    }
+
+    pub fn advance(&mut self, frame_time: f32) {
+        // Clamp long frames (window drags, breakpoints) so we don't spiral
+        self.accumulator += (frame_time as f64).min(MAX_FRAME_TIME);
+    }
+
+    pub fn step(&mut self) -> bool {
+        if self.accumulator < FIXED_TIMESTEP {
+            return false;
+        }
+        self.accumulator -= FIXED_TIMESTEP;
+        self.tick(FIXED_TIMESTEP as f32);
+        true
+    }
+
+    pub fn alpha(&self) -> f32 {
+        (self.accumulator / FIXED_TIMESTEP) as f32
+    }
+}
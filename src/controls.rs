@@ -0,0 +1,282 @@
+use crate::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+// Keys that can be bound, named in settings.json without the KEY_ prefix
+const KEYS: [KeyboardKey; 53] = [
+    KeyboardKey::KEY_A,
+    KeyboardKey::KEY_B,
+    KeyboardKey::KEY_C,
+    KeyboardKey::KEY_D,
+    KeyboardKey::KEY_E,
+    KeyboardKey::KEY_F,
+    KeyboardKey::KEY_G,
+    KeyboardKey::KEY_H,
+    KeyboardKey::KEY_I,
+    KeyboardKey::KEY_J,
+    KeyboardKey::KEY_K,
+    KeyboardKey::KEY_L,
+    KeyboardKey::KEY_M,
+    KeyboardKey::KEY_N,
+    KeyboardKey::KEY_O,
+    KeyboardKey::KEY_P,
+    KeyboardKey::KEY_Q,
+    KeyboardKey::KEY_R,
+    KeyboardKey::KEY_S,
+    KeyboardKey::KEY_T,
+    KeyboardKey::KEY_U,
+    KeyboardKey::KEY_V,
+    KeyboardKey::KEY_W,
+    KeyboardKey::KEY_X,
+    KeyboardKey::KEY_Y,
+    KeyboardKey::KEY_Z,
+    KeyboardKey::KEY_ZERO,
+    KeyboardKey::KEY_ONE,
+    KeyboardKey::KEY_TWO,
+    KeyboardKey::KEY_THREE,
+    KeyboardKey::KEY_FOUR,
+    KeyboardKey::KEY_FIVE,
+    KeyboardKey::KEY_SIX,
+    KeyboardKey::KEY_SEVEN,
+    KeyboardKey::KEY_EIGHT,
+    KeyboardKey::KEY_NINE,
+    KeyboardKey::KEY_SPACE,
+    KeyboardKey::KEY_ENTER,
+    KeyboardKey::KEY_TAB,
+    KeyboardKey::KEY_BACKSPACE,
+    KeyboardKey::KEY_ESCAPE,
+    KeyboardKey::KEY_UP,
+    KeyboardKey::KEY_DOWN,
+    KeyboardKey::KEY_LEFT,
+    KeyboardKey::KEY_RIGHT,
+    KeyboardKey::KEY_LEFT_SHIFT,
+    KeyboardKey::KEY_RIGHT_SHIFT,
+    KeyboardKey::KEY_LEFT_CONTROL,
+    KeyboardKey::KEY_RIGHT_CONTROL,
+    KeyboardKey::KEY_LEFT_ALT,
+    KeyboardKey::KEY_RIGHT_ALT,
+    KeyboardKey::KEY_COMMA,
+    KeyboardKey::KEY_PERIOD,
+];
+
+// Named without the GAMEPAD_BUTTON_ prefix
+const BUTTONS: [GamepadButton; 17] = [
+    GamepadButton::GAMEPAD_BUTTON_LEFT_FACE_UP,
+    GamepadButton::GAMEPAD_BUTTON_LEFT_FACE_RIGHT,
+    GamepadButton::GAMEPAD_BUTTON_LEFT_FACE_DOWN,
+    GamepadButton::GAMEPAD_BUTTON_LEFT_FACE_LEFT,
+    GamepadButton::GAMEPAD_BUTTON_RIGHT_FACE_UP,
+    GamepadButton::GAMEPAD_BUTTON_RIGHT_FACE_RIGHT,
+    GamepadButton::GAMEPAD_BUTTON_RIGHT_FACE_DOWN,
+    GamepadButton::GAMEPAD_BUTTON_RIGHT_FACE_LEFT,
+    GamepadButton::GAMEPAD_BUTTON_LEFT_TRIGGER_1,
+    GamepadButton::GAMEPAD_BUTTON_LEFT_TRIGGER_2,
+    GamepadButton::GAMEPAD_BUTTON_RIGHT_TRIGGER_1,
+    GamepadButton::GAMEPAD_BUTTON_RIGHT_TRIGGER_2,
+    GamepadButton::GAMEPAD_BUTTON_MIDDLE_LEFT,
+    GamepadButton::GAMEPAD_BUTTON_MIDDLE,
+    GamepadButton::GAMEPAD_BUTTON_MIDDLE_RIGHT,
+    GamepadButton::GAMEPAD_BUTTON_LEFT_THUMB,
+    GamepadButton::GAMEPAD_BUTTON_RIGHT_THUMB,
+];
+
+pub fn key_name(key: KeyboardKey) -> String {
+    format!("{:?}", key).trim_start_matches("KEY_").to_string()
+}
+
+pub fn button_name(button: GamepadButton) -> String {
+    format!("{:?}", button)
+        .trim_start_matches("GAMEPAD_BUTTON_")
+        .to_string()
+}
+
+fn key_from_name(name: &str) -> Option<KeyboardKey> {
+    KEYS.into_iter().find(|key| key_name(*key) == name)
+}
+
+fn button_from_name(name: &str) -> Option<GamepadButton> {
+    BUTTONS
+        .into_iter()
+        .find(|button| button_name(*button) == name)
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Action {
+    MoveLeft,
+    MoveRight,
+    Jump,
+    Drop,
+    Confirm,
+    MenuUp,
+    MenuDown,
+    Pause,
+}
+
+impl Action {
+    pub const ALL: [Action; 8] = [
+        Action::MoveLeft,
+        Action::MoveRight,
+        Action::Jump,
+        Action::Drop,
+        Action::Confirm,
+        Action::MenuUp,
+        Action::MenuDown,
+        Action::Pause,
+    ];
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Binding {
+    #[serde(default)]
+    pub keys: Vec<String>,
+    #[serde(default)]
+    pub buttons: Vec<String>,
+}
+
+impl Binding {
+    fn new(keys: &[KeyboardKey], buttons: &[GamepadButton]) -> Self {
+        Self {
+            keys: keys.iter().map(|key| key_name(*key)).collect(),
+            buttons: buttons.iter().map(|button| button_name(*button)).collect(),
+        }
+    }
+
+    fn keys(&self) -> impl Iterator<Item = KeyboardKey> + '_ {
+        self.keys.iter().filter_map(|name| key_from_name(name))
+    }
+
+    fn buttons(&self) -> impl Iterator<Item = GamepadButton> + '_ {
+        self.buttons
+            .iter()
+            .filter_map(|name| button_from_name(name))
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Controls {
+    #[serde(default)]
+    pub bindings: HashMap<Action, Binding>,
+}
+
+impl Default for Controls {
+    fn default() -> Self {
+        use GamepadButton::*;
+        use KeyboardKey::*;
+
+        let bindings = HashMap::from([
+            (
+                Action::MoveLeft,
+                Binding::new(&[KEY_LEFT], &[GAMEPAD_BUTTON_LEFT_FACE_LEFT]),
+            ),
+            (
+                Action::MoveRight,
+                Binding::new(&[KEY_RIGHT], &[GAMEPAD_BUTTON_LEFT_FACE_RIGHT]),
+            ),
+            (
+                Action::Jump,
+                Binding::new(&[KEY_UP, KEY_SPACE], &[GAMEPAD_BUTTON_RIGHT_FACE_DOWN]),
+            ),
+            (
+                Action::Drop,
+                Binding::new(&[KEY_DOWN], &[GAMEPAD_BUTTON_LEFT_FACE_DOWN]),
+            ),
+            (
+                Action::Confirm,
+                Binding::new(&[KEY_ENTER], &[GAMEPAD_BUTTON_RIGHT_FACE_DOWN]),
+            ),
+            (
+                Action::MenuUp,
+                Binding::new(&[KEY_UP], &[GAMEPAD_BUTTON_LEFT_FACE_UP]),
+            ),
+            (
+                Action::MenuDown,
+                Binding::new(&[KEY_DOWN], &[GAMEPAD_BUTTON_LEFT_FACE_DOWN]),
+            ),
+            (
+                Action::Pause,
+                Binding::new(&[KEY_ESCAPE], &[GAMEPAD_BUTTON_MIDDLE_RIGHT]),
+            ),
+        ]);
+
+        Self { bindings }
+    }
+}
+
+impl Controls {
+    // Actions missing from a settings file keep their default binding
+    pub fn fill_defaults(&mut self) {
+        for (action, binding) in Controls::default().bindings {
+            self.bindings.entry(action).or_insert(binding);
+        }
+    }
+
+    fn binding(&self, action: Action) -> Option<&Binding> {
+        self.bindings.get(&action)
+    }
+
+    pub fn is_down(&self, rl: &RaylibHandle, action: Action) -> bool {
+        self.binding(action).is_some_and(|binding| {
+            binding.keys().any(|key| rl.is_key_down(key))
+                || (rl.is_gamepad_available(GAMEPAD)
+                    && binding
+                        .buttons()
+                        .any(|button| rl.is_gamepad_button_down(GAMEPAD, button)))
+        })
+    }
+
+    pub fn is_pressed(&self, rl: &RaylibHandle, action: Action) -> bool {
+        self.binding(action).is_some_and(|binding| {
+            binding.keys().any(|key| rl.is_key_pressed(key))
+                || (rl.is_gamepad_available(GAMEPAD)
+                    && binding
+                        .buttons()
+                        .any(|button| rl.is_gamepad_button_pressed(GAMEPAD, button)))
+        })
+    }
+
+    // Name of the first key bound to an action, for on screen prompts
+    pub fn label(&self, action: Action) -> String {
+        self.binding(action)
+            .and_then(|binding| binding.keys.first())
+            .cloned()
+            .unwrap_or_else(|| "?".to_string())
+    }
+
+    // Keys and buttons missing from the tables could not be read back, so they are refused
+    pub fn bind_key(&mut self, action: Action, key: KeyboardKey) -> bool {
+        if !KEYS.contains(&key) {
+            return false;
+        }
+        self.bindings.entry(action).or_default().keys = vec![key_name(key)];
+        true
+    }
+
+    pub fn bind_button(&mut self, action: Action, button: GamepadButton) -> bool {
+        if !BUTTONS.contains(&button) {
+            return false;
+        }
+        self.bindings.entry(action).or_default().buttons = vec![button_name(button)];
+        true
+    }
+}
+
+// Read here with the bindings, so the simulation only ever sees the resulting Input
+impl Input {
+    pub fn read(game_handle: &RaylibHandle, controls: &Controls) -> Self {
+        let (stick, stick_y) = if game_handle.is_gamepad_available(GAMEPAD) {
+            (
+                game_handle.get_gamepad_axis_movement(GAMEPAD, GamepadAxis::GAMEPAD_AXIS_LEFT_X),
+                game_handle.get_gamepad_axis_movement(GAMEPAD, GamepadAxis::GAMEPAD_AXIS_LEFT_Y),
+            )
+        } else {
+            (0.0, 0.0)
+        };
+
+        Self {
+            left: controls.is_down(game_handle, Action::MoveLeft) || stick < -GAMEPAD_DEADZONE,
+            right: controls.is_down(game_handle, Action::MoveRight) || stick > GAMEPAD_DEADZONE,
+            jump: controls.is_down(game_handle, Action::Jump),
+            down: controls.is_down(game_handle, Action::Drop) || stick_y > GAMEPAD_DEADZONE,
+        }
+    }
+}
@@ -1,302 +1,247 @@
-use raylib::prelude::*;
+use crate::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
 
-#[derive(Clone)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct DialogueLine {
     pub speaker: String,
     pub text: String,
     pub wait_after: f32,
+    #[serde(default)]
     pub sound_effect: Option<String>,
+    #[serde(default)]
+    pub blip: Option<String>, // Sound cue for each revealed character
 }
 
-pub struct DialogueSystem {
-    lines: Vec<DialogueLine>,
-    current_line: usize,
-    line_start_time: f64,
-    dialogue_started: bool,
-    font: Font,
-    showing_choice: bool,
+// Checked against the run when choices are shown or a node picks where to go next
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum Condition {
+    Flag(String),
+    NotFlag(String),
+    LivesAtLeast(u32),
+    LivesAtMost(u32),
+    AgeAtLeast(usize), // Index into the life cycle stages at arrival
+    AgeAtMost(usize),
 }
 
-impl DialogueSystem {
-    pub fn new(
-        rl: &mut RaylibHandle,
-        thread: &RaylibThread,
-    ) -> Result<Self, Box<dyn std::error::Error>> {
-        let font = rl.load_font(thread, "src/assets/tiny.ttf")?;
-
-        let lines = vec![
-            DialogueLine {
-                speaker: "???".to_string(),
-                text: "Again... so soon?".to_string(),
-                wait_after: 3.5,
-                sound_effect: Some("drip".to_string()),
-            },
-            DialogueLine {
-                speaker: "???".to_string(),
-                text: "You claw your way back through the dark,\nlifetime after lifetime..."
-                    .to_string(),
-                wait_after: 4.0,
-                sound_effect: None,
-            },
-            DialogueLine {
-                speaker: "Devil".to_string(),
-                text: "Tell me - don't you ever tire of this?".to_string(),
-                wait_after: 4.0,
-                sound_effect: None,
-            },
-            DialogueLine {
-                speaker: "You".to_string(),
-                text: "You've put me through hell!".to_string(),
-                wait_after: 3.0,
-                sound_effect: None,
-            },
-            DialogueLine {
-                speaker: "You".to_string(),
-                text: "Don't you ever get tired of watching\nme suffer?!".to_string(),
-                wait_after: 4.0,
-                sound_effect: None,
-            },
-            DialogueLine {
-                speaker: "Devil".to_string(),
-                text: "Hell?".to_string(),
-                wait_after: 2.5,
-                sound_effect: None,
-            },
-            DialogueLine {
-                speaker: "Devil".to_string(),
-                text: "You speak as though it wasn't you\nwho begged for this.".to_string(),
-                wait_after: 4.0,
-                sound_effect: None,
-            },
-            DialogueLine {
-                speaker: "Devil".to_string(),
-                text: "Immortality… the gift you wanted most.".to_string(),
-                wait_after: 3.5,
-                sound_effect: Some("laugh".to_string()),
-            },
-            DialogueLine {
-                speaker: "Devil".to_string(),
-                text: "And this is how you thank me?".to_string(),
-                wait_after: 3.5,
-                sound_effect: None,
-            },
-            DialogueLine {
-                speaker: "You".to_string(),
-                text: "Give me my life back!".to_string(),
-                wait_after: 3.0,
-                sound_effect: None,
-            },
-            DialogueLine {
-                speaker: "Devil".to_string(),
-                text: "Your life?".to_string(),
-                wait_after: 2.5,
-                sound_effect: None,
-            },
-            DialogueLine {
-                speaker: "Devil".to_string(),
-                text: "You threw it away long ago.".to_string(),
-                wait_after: 3.5,
-                sound_effect: None,
-            },
-            DialogueLine {
-                speaker: "Devil".to_string(),
-                text: "What you live now is eternity.".to_string(),
-                wait_after: 4.0,
-                sound_effect: None,
-            },
-            DialogueLine {
-                speaker: "You".to_string(),
-                text: "Immortality is meaningless\nwithout purpose.".to_string(),
-                wait_after: 4.0,
-                sound_effect: None,
-            },
-            DialogueLine {
-                speaker: "You".to_string(),
-                text: "Without an end,\nit's just another prison…".to_string(),
-                wait_after: 4.0,
-                sound_effect: None,
-            },
-            DialogueLine {
-                speaker: "Devil".to_string(),
-                text: "Ah… purpose.".to_string(),
-                wait_after: 2.5,
-                sound_effect: None,
-            },
-            DialogueLine {
-                speaker: "Devil".to_string(),
-                text: "Such a fragile word.".to_string(),
-                wait_after: 3.0,
-                sound_effect: None,
-            },
-            DialogueLine {
-                speaker: "Devil".to_string(),
-                text: "Very well, old friend…".to_string(),
-                wait_after: 3.5,
-                sound_effect: None,
-            },
-            DialogueLine {
-                speaker: "Devil".to_string(),
-                text: "Shall I take back my gift?".to_string(),
-                wait_after: 3.5,
-                sound_effect: None,
-            },
-            DialogueLine {
-                speaker: "Devil".to_string(),
-                text: "Shall I let you crumble into dust at last?".to_string(),
-                wait_after: 4.5,
-                sound_effect: None,
-            },
-            DialogueLine {
-                speaker: "Devil".to_string(),
-                text: "Or will you stay… crawling through the dark\nfor another thousand years…"
-                    .to_string(),
-                wait_after: 4.5,
-                sound_effect: None,
-            },
-            DialogueLine {
-                speaker: "Devil".to_string(),
-                text: "…chasing my shadow?".to_string(),
-                wait_after: 5.0,
-                sound_effect: None,
-            },
-            DialogueLine {
-                speaker: "Devil".to_string(),
-                text: "What will it be, old friend?".to_string(),
-                wait_after: 8.0,
-                sound_effect: None,
-            },
-        ];
-
-        Ok(Self {
-            lines,
-            current_line: 0,
-            line_start_time: 0.0,
-            dialogue_started: false,
-            font,
-            showing_choice: false,
-        })
+impl Condition {
+    pub fn holds(&self, simulation: &Simulation) -> bool {
+        let player = &simulation.player;
+        match self {
+            Self::Flag(flag) => simulation.flags.contains(flag),
+            Self::NotFlag(flag) => !simulation.flags.contains(flag),
+            Self::LivesAtLeast(lives) => player.lives >= *lives,
+            Self::LivesAtMost(lives) => player.lives <= *lives,
+            Self::AgeAtLeast(age) => player.age.0 >= *age,
+            Self::AgeAtMost(age) => player.age.0 <= *age,
+        }
     }
+}
 
-    pub fn start(&mut self, current_time: f64) {
-        self.dialogue_started = true;
-        self.line_start_time = current_time;
-        self.current_line = 0;
-    }
+pub fn all_hold(conditions: &[Condition], simulation: &Simulation) -> bool {
+    conditions
+        .iter()
+        .all(|condition| condition.holds(simulation))
+}
 
-    pub fn update(&mut self, current_time: f64) -> Option<String> {
-        if !self.dialogue_started {
-            return None;
-        }
+// Handed back to the game to apply once the dialogue reaches them
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum Outcome {
+    SetFlag(String),
+    ClearFlag(String),
+    Respawn,   // Back to the start of the level with a new life
+    NextLevel, // On to the next campaign level with a new life
+    GameOver,  // Ends the run, the game moves on to the credits
+}
 
-        if self.current_line >= self.lines.len() {
-            self.showing_choice = true;
-            return None;
-        }
+#[derive(Serialize, Deserialize, Clone)]
+pub struct DialogueChoice {
+    pub text: String,
+    #[serde(default)]
+    pub conditions: Vec<Condition>,
+    #[serde(default)]
+    pub outcomes: Vec<Outcome>,
+    #[serde(default)]
+    pub next: Option<String>,
+}
 
-        let elapsed = current_time - self.line_start_time;
-        let current = &self.lines[self.current_line];
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Branch {
+    #[serde(default)]
+    pub conditions: Vec<Condition>,
+    pub node: String,
+}
 
-        if elapsed >= current.wait_after as f64 {
-            let sound_effect = current.sound_effect.clone();
-            self.current_line += 1;
-            self.line_start_time = current_time;
-            return sound_effect;
-        }
+#[derive(Serialize, Deserialize, Clone)]
+pub struct DialogueNode {
+    #[serde(default)]
+    pub lines: Vec<DialogueLine>,
+    #[serde(default)]
+    pub outcomes: Vec<Outcome>, // Applied when the node is entered
+    #[serde(default)]
+    pub choices: Vec<DialogueChoice>,
+    #[serde(default)]
+    pub next: Vec<Branch>, // Without choices, the first branch whose conditions hold
+}
 
-        None
-    }
+#[derive(Serialize, Deserialize, Clone)]
+pub struct DialogueScript {
+    pub start: String,
+    pub nodes: HashMap<String, DialogueNode>,
+}
 
-    pub fn handle_choice(&self, rl: &RaylibHandle) -> Option<bool> {
-        if !self.showing_choice {
-            return None;
+impl DialogueScript {
+    fn validate(&self) -> Result<(), String> {
+        let targets = self.nodes.values().flat_map(|node| {
+            let choices = node
+                .choices
+                .iter()
+                .filter_map(|choice| choice.next.as_ref());
+            let branches = node.next.iter().map(|branch| &branch.node);
+            choices.chain(branches)
+        });
+
+        for target in std::iter::once(&self.start).chain(targets) {
+            if !self.nodes.contains_key(target) {
+                return Err(format!("unknown dialogue node \"{}\"", target));
+            }
         }
+        Ok(())
+    }
+}
 
-        if rl.is_key_pressed(KeyboardKey::KEY_L) {
-            return Some(true); // Live
-        } else if rl.is_key_pressed(KeyboardKey::KEY_D) {
-            return Some(false); // Die
+// Without a dialogue the ending has nothing to say and starts a new life, like any without an outcome
+impl Default for DialogueScript {
+    fn default() -> Self {
+        let start = "arrival".to_string();
+        let node = DialogueNode {
+            lines: Vec::new(),
+            outcomes: Vec::new(),
+            choices: Vec::new(),
+            next: Vec::new(),
+        };
+        Self {
+            nodes: HashMap::from([(start.clone(), node)]),
+            start,
         }
-
-        None
     }
+}
 
-    pub fn draw<D: RaylibDraw>(&mut self, d: &mut D, screen_width: i32, screen_height: i32) {
-        if !self.dialogue_started {
-            return;
+// Checked once at startup so a broken file cannot end the game at the ending
+pub fn load_script() -> DialogueScript {
+    match fs::read_to_string(DIALOGUE_PATH) {
+        Ok(content) => match serde_json::from_str::<DialogueScript>(&content) {
+            Ok(script) => match script.validate() {
+                Ok(()) => {
+                    println!("Loaded {} with {} nodes", DIALOGUE_PATH, script.nodes.len());
+                    script
+                }
+                Err(e) => {
+                    eprintln!("Invalid {}: {}", DIALOGUE_PATH, e);
+                    DialogueScript::default()
+                }
+            },
+            Err(e) => {
+                eprintln!("Failed to parse {}: {}", DIALOGUE_PATH, e);
+                DialogueScript::default()
+            }
+        },
+        Err(_) => {
+            println!("No {} found, the ending has no dialogue.", DIALOGUE_PATH);
+            DialogueScript::default()
         }
+    }
+}
 
-        if self.showing_choice {
-            // Draw choice prompt
-            let base_dim = screen_width.min(screen_height) as f32;
-            let font_size = (base_dim * 0.045).clamp(16.0, 32.0);
-            let spacing = font_size * 0.1;
-            let padding_x = screen_width as f32 * 0.04;
-            let padding_y = screen_height as f32 * 0.05;
-            let line_spacing = font_size * 0.25;
-
-            let red_color = Color::new(235, 219, 178, 255);
-
-            let choice_text = "[L] to live    [D] to die";
-            let total_text_height = font_size;
-            let y_pos = screen_height as f32 - padding_y - total_text_height;
-
-            d.draw_text_ex(
-                &self.font,
-                choice_text,
-                Vector2::new(padding_x, y_pos),
-                font_size,
-                spacing,
-                red_color,
-            );
-            return;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::age::{Age, LifeCycle};
+    use crate::campaign::Campaign;
+
+    fn node(next: &[&str], choices: &[Option<&str>]) -> DialogueNode {
+        DialogueNode {
+            lines: Vec::new(),
+            outcomes: Vec::new(),
+            choices: choices
+                .iter()
+                .map(|next| DialogueChoice {
+                    text: "...".to_string(),
+                    conditions: Vec::new(),
+                    outcomes: Vec::new(),
+                    next: next.map(str::to_string),
+                })
+                .collect(),
+            next: next
+                .iter()
+                .map(|node| Branch {
+                    conditions: Vec::new(),
+                    node: node.to_string(),
+                })
+                .collect(),
         }
+    }
 
-        if self.current_line >= self.lines.len() {
-            return;
+    fn script(start: &str, nodes: Vec<(&str, DialogueNode)>) -> DialogueScript {
+        DialogueScript {
+            start: start.to_string(),
+            nodes: nodes
+                .into_iter()
+                .map(|(name, node)| (name.to_string(), node))
+                .collect(),
         }
+    }
 
-        let current = &self.lines[self.current_line];
-
-        // Font sizing relative to base resolution
-        let base_dim = screen_width.min(screen_height) as f32;
-        let font_size = (base_dim * 0.045).clamp(16.0, 32.0);
-        let spacing = font_size * 0.1;
-        let padding_x = screen_width as f32 * 0.04;
-        let padding_y = screen_height as f32 * 0.05;
-        let line_spacing = font_size * 0.25;
-
-        // Color per speaker
-        let color = match current.speaker.as_str() {
-            "Devil" | "???" => Color::new(251, 73, 52, 255),
-            "You" => Color::new(235, 219, 178, 255),
-            _ => Color::new(168, 153, 132, 255),
-        };
-
-        // Split into lines
-        let lines: Vec<&str> = current.text.split('\n').collect();
+    #[test]
+    fn validate_accepts_a_script_whose_targets_all_exist() {
+        let nodes = vec![
+            ("arrival", node(&["pact"], &[])),
+            ("pact", node(&[], &[Some("arrival"), None])),
+        ];
+        assert!(script("arrival", nodes).validate().is_ok());
+    }
 
-        // Compute total height so it sits neatly above bottom padding
-        let total_text_height = lines.len() as f32 * (font_size + line_spacing) - line_spacing;
-        let y_start = screen_height as f32 - padding_y - total_text_height;
+    #[test]
+    fn validate_rejects_dangling_targets() {
+        let missing_start = script("nowhere", vec![("arrival", node(&[], &[]))]);
+        assert!(missing_start.validate().is_err());
 
-        // Draw each line left-aligned
-        for (i, line) in lines.iter().enumerate() {
-            let height = font_size + line_spacing;
-            let x_pos = padding_x;
-            let y_pos = y_start + (i as f32 * height);
+        let missing_next = script("arrival", vec![("arrival", node(&["nowhere"], &[]))]);
+        assert!(missing_next.validate().is_err());
 
-            d.draw_text_ex(
-                &self.font,
-                line,
-                Vector2::new(x_pos, y_pos),
-                font_size,
-                spacing,
-                color,
-            );
-        }
+        let missing_choice = script("arrival", vec![("arrival", node(&[], &[Some("nowhere")]))]);
+        assert!(missing_choice.validate().is_err());
     }
 
-    pub fn is_finished(&self) -> bool {
-        self.current_line >= self.lines.len()
+    #[test]
+    fn conditions_hold_against_the_run() {
+        let levels = vec![(MapHeader::default(), WorldMap::new(4, 4))];
+        let mut simulation =
+            Simulation::new(Campaign::single("test.json"), levels, LifeCycle::default());
+        simulation.flags.insert("warned".to_string());
+        simulation.player.lives = 3;
+        simulation.player.age = Age(2);
+
+        let holds = |condition: Condition| condition.holds(&simulation);
+        assert!(holds(Condition::Flag("warned".to_string())));
+        assert!(!holds(Condition::Flag("pact".to_string())));
+        assert!(holds(Condition::NotFlag("pact".to_string())));
+        assert!(!holds(Condition::NotFlag("warned".to_string())));
+        assert!(holds(Condition::LivesAtLeast(3)));
+        assert!(!holds(Condition::LivesAtLeast(4)));
+        assert!(holds(Condition::LivesAtMost(3)));
+        assert!(!holds(Condition::LivesAtMost(2)));
+        assert!(holds(Condition::AgeAtLeast(2)));
+        assert!(!holds(Condition::AgeAtLeast(3)));
+        assert!(holds(Condition::AgeAtMost(2)));
+        assert!(!holds(Condition::AgeAtMost(1)));
+
+        let conditions = [
+            Condition::Flag("warned".to_string()),
+            Condition::LivesAtLeast(4),
+        ];
+        assert!(!all_hold(&conditions, &simulation));
+        assert!(all_hold(&conditions[..1], &simulation));
     }
 }
@@ -0,0 +1,325 @@
+use crate::dialogue::{DialogueNode, DialogueScript, Outcome, all_hold};
+use crate::settings::DialogueAdvance;
+use crate::*;
+
+pub struct DialogueSystem {
+    script: DialogueScript,
+    node: String,
+    current_line: usize,
+    line_start_time: f64,
+    revealed: usize,            // Characters of the current line shown so far
+    revealed_time: Option<f64>, // When the whole line became visible
+    advance_requested: bool,
+    advance: DialogueAdvance,
+    dialogue_started: bool,
+    finished: bool,
+    choices: Vec<usize>, // Indices of the choices whose conditions held
+    selected: usize,
+    outcomes: Vec<Outcome>,
+    choice_hint: String,
+    continue_hint: String,
+}
+
+impl DialogueSystem {
+    pub fn new(script: &DialogueScript, settings: &Settings) -> Self {
+        let controls = &settings.controls;
+
+        Self {
+            node: script.start.clone(),
+            script: script.clone(),
+            current_line: 0,
+            line_start_time: 0.0,
+            revealed: 0,
+            revealed_time: None,
+            advance_requested: false,
+            advance: settings.dialogue_advance,
+            dialogue_started: false,
+            finished: false,
+            choices: Vec::new(),
+            selected: 0,
+            outcomes: Vec::new(),
+            choice_hint: format!(
+                "[{}]/[{}] to choose    [{}] to confirm",
+                controls.label(Action::MoveLeft),
+                controls.label(Action::MoveRight),
+                controls.label(Action::Confirm)
+            ),
+            continue_hint: format!("[{}] to continue", controls.label(Action::Confirm)),
+        }
+    }
+
+    pub fn start(&mut self, current_time: f64) {
+        let start = self.script.start.clone();
+        self.start_at(&start, current_time);
+    }
+
+    // Unknown nodes fall back to the start of the script
+    pub fn start_at(&mut self, node: &str, current_time: f64) {
+        let node = if self.script.nodes.contains_key(node) {
+            node.to_string()
+        } else {
+            eprintln!("Unknown dialogue node \"{}\", starting from the top", node);
+            self.script.start.clone()
+        };
+        self.dialogue_started = true;
+        self.finished = false;
+        self.enter(node, current_time);
+    }
+
+    fn node(&self) -> &DialogueNode {
+        &self.script.nodes[&self.node]
+    }
+
+    fn enter(&mut self, node: String, current_time: f64) {
+        self.node = node;
+        self.start_line(0, current_time);
+        self.choices.clear();
+        self.selected = 0;
+        let outcomes = self.node().outcomes.clone();
+        self.outcomes.extend(outcomes);
+    }
+
+    fn start_line(&mut self, line: usize, current_time: f64) {
+        self.current_line = line;
+        self.line_start_time = current_time;
+        self.revealed = 0;
+        self.revealed_time = None;
+        self.advance_requested = false;
+    }
+
+    fn showing_choice(&self) -> bool {
+        !self.choices.is_empty()
+    }
+
+    pub fn update(&mut self, current_time: f64, simulation: &Simulation) -> Option<String> {
+        if !self.dialogue_started || self.finished || self.showing_choice() {
+            return None;
+        }
+
+        let node = self.node();
+        if self.current_line >= node.lines.len() {
+            let choices: Vec<usize> = (0..node.choices.len())
+                .filter(|i| all_hold(&node.choices[*i].conditions, simulation))
+                .collect();
+            let next = node
+                .next
+                .iter()
+                .find(|branch| all_hold(&branch.conditions, simulation))
+                .map(|branch| branch.node.clone());
+
+            if !choices.is_empty() {
+                self.choices = choices;
+            } else {
+                match next {
+                    Some(next) => self.enter(next, current_time),
+                    None => self.finished = true,
+                }
+            }
+            return None;
+        }
+
+        let current = &node.lines[self.current_line];
+        let length = current.text.chars().count();
+
+        // Reveal characters over time, skipping ahead never goes backwards
+        let elapsed = current_time - self.line_start_time;
+        let shown = ((elapsed * DIALOGUE_REVEAL_SPEED) as usize)
+            .max(self.revealed)
+            .min(length);
+        if shown > self.revealed {
+            let blip = current.blip.clone().filter(|_| {
+                current
+                    .text
+                    .chars()
+                    .nth(shown - 1)
+                    .is_some_and(|c| !c.is_whitespace())
+            });
+            self.revealed = shown;
+            if shown == length {
+                self.revealed_time = Some(current_time);
+            }
+            return blip;
+        }
+
+        let Some(revealed_time) = self.revealed_time else {
+            self.revealed_time = Some(current_time);
+            return None;
+        };
+
+        let waited = current_time - revealed_time >= current.wait_after as f64;
+        if self.advance_requested || (self.advance == DialogueAdvance::Auto && waited) {
+            let sound_effect = current.sound_effect.clone();
+            self.start_line(self.current_line + 1, current_time);
+            return sound_effect;
+        }
+
+        None
+    }
+
+    pub fn handle_input(&mut self, rl: &RaylibHandle, controls: &Controls, current_time: f64) {
+        if !self.dialogue_started || self.finished {
+            return;
+        }
+
+        if !self.showing_choice() {
+            // Confirm first completes the line, then moves past it
+            if controls.is_pressed(rl, Action::Confirm) {
+                match self.node().lines.get(self.current_line) {
+                    Some(line) if self.revealed < line.text.chars().count() => {
+                        self.revealed = line.text.chars().count();
+                        self.revealed_time = Some(current_time);
+                    }
+                    Some(_) => self.advance_requested = true,
+                    None => {}
+                }
+            }
+            return;
+        }
+
+        if controls.is_pressed(rl, Action::MoveLeft) {
+            self.selected = self.selected.saturating_sub(1);
+        } else if controls.is_pressed(rl, Action::MoveRight) {
+            self.selected = (self.selected + 1).min(self.choices.len() - 1);
+        } else if controls.is_pressed(rl, Action::Confirm) {
+            let choice = self.node().choices[self.choices[self.selected]].clone();
+            self.outcomes.extend(choice.outcomes);
+            match choice.next {
+                Some(next) => self.enter(next, current_time),
+                None => {
+                    self.choices.clear();
+                    self.finished = true;
+                }
+            }
+        }
+    }
+
+    // Outcomes reached since the last call, in the order they happened
+    pub fn take_outcomes(&mut self) -> Vec<Outcome> {
+        std::mem::take(&mut self.outcomes)
+    }
+
+    pub fn draw<D: RaylibDraw>(
+        &mut self,
+        d: &mut D,
+        font: &Font,
+        screen_width: i32,
+        screen_height: i32,
+    ) {
+        if !self.dialogue_started || self.finished {
+            return;
+        }
+
+        if self.showing_choice() {
+            // Draw choice prompt
+            let base_dim = screen_width.min(screen_height) as f32;
+            let font_size = (base_dim * 0.045).clamp(16.0, 32.0);
+            let spacing = font_size * 0.1;
+            let padding_x = screen_width as f32 * 0.04;
+            let padding_y = screen_height as f32 * 0.05;
+            let line_spacing = font_size * 0.25;
+
+            let selected_color = Color::new(251, 73, 52, 255);
+            let color = Color::new(235, 219, 178, 255);
+            let hint_color = Color::new(168, 153, 132, 255);
+
+            let total_text_height = font_size * 2.0 + line_spacing;
+            let y_pos = screen_height as f32 - padding_y - total_text_height;
+
+            let node = self.node();
+            let mut x_pos = padding_x;
+            for (i, choice) in self.choices.iter().enumerate() {
+                let text = &node.choices[*choice].text;
+                let color = if i == self.selected {
+                    selected_color
+                } else {
+                    color
+                };
+                d.draw_text_ex(
+                    font,
+                    text,
+                    Vector2::new(x_pos, y_pos),
+                    font_size,
+                    spacing,
+                    color,
+                );
+                x_pos += measure_text_ex(font, text, font_size, spacing).x + font_size * 2.0;
+            }
+
+            d.draw_text_ex(
+                font,
+                &self.choice_hint,
+                Vector2::new(padding_x, y_pos + font_size + line_spacing),
+                font_size * 0.6,
+                spacing,
+                hint_color,
+            );
+            return;
+        }
+
+        let node = self.node();
+        if self.current_line >= node.lines.len() {
+            return;
+        }
+
+        let current = &node.lines[self.current_line];
+
+        // Font sizing relative to base resolution
+        let base_dim = screen_width.min(screen_height) as f32;
+        let font_size = (base_dim * 0.045).clamp(16.0, 32.0);
+        let spacing = font_size * 0.1;
+        let padding_x = screen_width as f32 * 0.04;
+        let padding_y = screen_height as f32 * 0.05;
+        let line_spacing = font_size * 0.25;
+
+        // Color per speaker
+        let color = match current.speaker.as_str() {
+            "Devil" | "???" => Color::new(251, 73, 52, 255),
+            "You" => Color::new(235, 219, 178, 255),
+            _ => Color::new(168, 153, 132, 255),
+        };
+
+        // Split the revealed part into lines
+        let text: String = current.text.chars().take(self.revealed).collect();
+        let lines: Vec<&str> = text.split('\n').collect();
+
+        // Compute total height so it sits neatly above bottom padding
+        let total_text_height = lines.len() as f32 * (font_size + line_spacing) - line_spacing;
+        let y_start = screen_height as f32 - padding_y - total_text_height;
+
+        // Draw each line left-aligned
+        for (i, line) in lines.iter().enumerate() {
+            let height = font_size + line_spacing;
+            let x_pos = padding_x;
+            let y_pos = y_start + (i as f32 * height);
+
+            d.draw_text_ex(
+                font,
+                line,
+                Vector2::new(x_pos, y_pos),
+                font_size,
+                spacing,
+                color,
+            );
+        }
+
+        if self.advance == DialogueAdvance::Manual && self.revealed_time.is_some() {
+            let hint_size = font_size * 0.6;
+            let width = measure_text_ex(font, &self.continue_hint, hint_size, spacing).x;
+            d.draw_text_ex(
+                font,
+                &self.continue_hint,
+                Vector2::new(
+                    screen_width as f32 - padding_x - width,
+                    screen_height as f32 - padding_y - hint_size,
+                ),
+                hint_size,
+                spacing,
+                Color::new(168, 153, 132, 255),
+            );
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+}
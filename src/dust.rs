@@ -1,5 +1,4 @@
 use crate::*;
-use raylib::prelude::*;
 use std::error::Error;
 
 pub struct Dust {
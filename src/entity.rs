@@ -0,0 +1,162 @@
+use crate::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum EntityKind {
+    Devil,   // Waits at the end of a level, the ending starts near it
+    Boulder, // Pushed by a strong enough player, falls and tips off ledges
+}
+
+impl EntityKind {
+    pub fn size(&self) -> (f32, f32) {
+        match self {
+            Self::Devil => (SPRITE_SIZE, DEVIL_HEIGHT),
+            Self::Boulder => (BOULDER_SIZE, BOULDER_SIZE),
+        }
+    }
+
+    pub fn is_pushable(&self) -> bool {
+        matches!(self, Self::Boulder)
+    }
+
+    pub fn has_gravity(&self) -> bool {
+        matches!(self, Self::Boulder)
+    }
+}
+
+// Where an entity starts, kept in the map file on the tile grid
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct EntitySpawn {
+    pub kind: EntityKind,
+    pub x: usize,
+    pub y: usize,
+}
+
+#[derive(Clone, Debug)]
+pub struct Entity {
+    pub kind: EntityKind,
+    pub body: Rect, // Collider, the sprite is drawn over it
+    pub previous_position: (f32, f32),
+    pub vel: (f32, f32),
+    pub grounded: bool,
+    pub angle: f32, // Radians rolled so far, only for drawing
+}
+
+impl Entity {
+    pub fn new(kind: EntityKind, x: f32, y: f32) -> Self {
+        let (width, height) = kind.size();
+        Self {
+            kind,
+            body: Rect {
+                x,
+                y,
+                width,
+                height,
+            },
+            previous_position: (x, y),
+            vel: (0.0, 0.0),
+            grounded: false,
+            angle: 0.0,
+        }
+    }
+
+    // Entities stand on the bottom of their tile, tall ones reach up above it
+    pub fn spawn(spawn: &EntitySpawn) -> Self {
+        let (_, height) = spawn.kind.size();
+        Self::new(
+            spawn.kind,
+            (spawn.x as f32) * BLOCK_SIZE as f32,
+            ((spawn.y + 1) as f32) * BLOCK_SIZE as f32 - height,
+        )
+    }
+
+    pub fn position(&self) -> (f32, f32) {
+        (self.body.x, self.body.y)
+    }
+
+    // `others` are the bodies of the level's other entities, which block like walls
+    pub fn update(&mut self, map: &WorldMap, others: &[Rect]) {
+        self.previous_position = self.position();
+
+        if !self.kind.has_gravity() {
+            return;
+        }
+
+        // With only one side resting on something it rolls towards the other
+        if self.grounded {
+            let left = self.supported(map, self.body.x + 1.0);
+            let right = self.supported(map, self.body.x + self.body.width - 1.0);
+            match (left, right) {
+                (true, false) => self.vel.0 += BOULDER_ROLL_ACCELERATION,
+                (false, true) => self.vel.0 -= BOULDER_ROLL_ACCELERATION,
+                _ => self.vel.0 *= BOULDER_FRICTION,
+            }
+        }
+
+        self.vel.1 += GRAVITY;
+
+        self.move_x(self.vel.0, map, others);
+
+        let previous_bottom = (self.vel.1 > 0.0).then_some(self.body.y + self.body.height);
+        self.body.y += self.vel.1;
+        if let Some(block) = self.collides(map, others, previous_bottom) {
+            if self.vel.1 > 0.0 {
+                self.body.y = block.y - self.body.height;
+                self.grounded = true;
+            } else if self.vel.1 < 0.0 {
+                self.body.y = block.y + block.height;
+            }
+            self.vel.1 = 0.0;
+        } else {
+            self.grounded = false;
+        }
+    }
+
+    // Moves sideways as far as the map lets it and returns how far that was
+    pub fn move_x(&mut self, dx: f32, map: &WorldMap, others: &[Rect]) -> f32 {
+        let start = self.body.x;
+        self.body.x += dx;
+        if let Some(block) = self.collides(map, others, None) {
+            if dx > 0.0 {
+                self.body.x = block.x - self.body.width;
+            } else if dx < 0.0 {
+                self.body.x = block.x + block.width;
+            }
+            self.vel.0 = 0.0;
+        }
+
+        let moved = self.body.x - start;
+        self.angle += moved / (self.body.width / 2.0);
+        moved
+    }
+
+    // Something solid one pixel under the given point of the bottom edge
+    fn supported(&self, map: &WorldMap, x: f32) -> bool {
+        let (tile_x, tile_y) = WorldMap::tile_at(x, self.body.y + self.body.height + 1.0);
+        tile_x >= 0
+            && tile_y >= 0
+            && map
+                .get((tile_x as usize, tile_y as usize))
+                .is_some_and(|b| b.is_collidable())
+    }
+
+    // Same rule for one-way tiles as the player's, other entities are solid from every side
+    fn collides(
+        &self,
+        map: &WorldMap,
+        others: &[Rect],
+        previous_bottom: Option<f32>,
+    ) -> Option<Rect> {
+        map.blocks_in(&self.body, 0)
+            .map(|((x, y), b)| (b, Rect::tile(x, y)))
+            .filter(|(b, block_rect)| b.stops(block_rect.y, previous_bottom))
+            .map(|(_, block_rect)| block_rect)
+            .find(|block_rect| block_rect.overlaps(&self.body))
+            .or_else(|| {
+                others
+                    .iter()
+                    .copied()
+                    .find(|other| other.overlaps(&self.body))
+            })
+    }
+}
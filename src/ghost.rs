@@ -0,0 +1,77 @@
+use crate::*;
+
+#[derive(Clone, Copy, Debug)]
+pub struct GhostFrame {
+    pub position: (f32, f32),
+    pub source: Rectangle, // Sprite sheet frame the player showed on this step
+}
+
+// Paths of earlier lives on the current level, played back step for step beside the player
+#[derive(Default)]
+pub struct Ghosts {
+    level: usize,
+    lives: Vec<Vec<GhostFrame>>,
+    current: Vec<GhostFrame>,
+}
+
+impl Ghosts {
+    pub fn record(&mut self, player: &Player, level: usize) {
+        // Paths from another level would walk through walls
+        if level != self.level {
+            self.clear();
+            self.level = level;
+        }
+
+        self.current.push(GhostFrame {
+            position: (player.body.x, player.body.y),
+            source: player.sprite_source().into(),
+        });
+    }
+
+    // The life that just ended becomes a ghost, the oldest one goes past the limit
+    pub fn end_life(&mut self) {
+        let life = std::mem::take(&mut self.current);
+        if life.is_empty() {
+            return;
+        }
+
+        self.lives.push(life);
+        if self.lives.len() > GHOST_LIMIT {
+            self.lives.remove(0);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.lives.clear();
+        self.current.clear();
+    }
+
+    pub fn draw<D: RaylibDraw>(&self, d: &mut D, sprite: &Texture2D, alpha: f32) {
+        let step = self.current.len().saturating_sub(1);
+
+        for (i, life) in self.lives.iter().enumerate() {
+            // Ghosts whose life was shorter than this one are gone
+            let Some(frame) = life.get(step) else {
+                continue;
+            };
+            let previous = life[step.saturating_sub(1)];
+
+            // Older lives are fainter
+            let opacity = GHOST_OPACITY as f32 * (i + 1) as f32 / self.lives.len() as f32;
+
+            d.draw_texture_pro(
+                sprite,
+                frame.source,
+                Rectangle {
+                    x: smoothing(previous.position.0, frame.position.0, alpha),
+                    y: smoothing(previous.position.1, frame.position.1, alpha),
+                    width: PLAYER_SCALE * SPRITE_SIZE,
+                    height: PLAYER_SCALE * SPRITE_SIZE,
+                },
+                Vector2::zero(),
+                0.0,
+                Color::new(255, 255, 255, opacity as u8),
+            );
+        }
+    }
+}
@@ -0,0 +1,215 @@
+use crate::*;
+
+#[derive(Clone, Copy, Debug)]
+struct CellChange {
+    pos: (usize, usize),
+    before: Option<BlockType>,
+    after: Option<BlockType>,
+}
+
+// A reversible map edit, stored as the cells it changed
+#[derive(Clone, Debug, Default)]
+pub struct Edit {
+    size_before: (usize, usize),
+    size_after: (usize, usize),
+    changes: Vec<CellChange>,
+    entities: Option<(Vec<EntitySpawn>, Vec<EntitySpawn>)>, // Before and after, if they changed
+    triggers: Option<(Vec<Trigger>, Vec<Trigger>)>,
+}
+
+impl Edit {
+    pub fn between(before: &WorldMap, after: &WorldMap) -> Self {
+        let mut changes = Vec::new();
+        for y in 0..before.height().max(after.height()) {
+            for x in 0..before.width().max(after.width()) {
+                let pos = (x, y);
+                let (b, a) = (before.get(pos), after.get(pos));
+                if b != a {
+                    changes.push(CellChange {
+                        pos,
+                        before: b,
+                        after: a,
+                    });
+                }
+            }
+        }
+        let entities = (before.entities() != after.entities())
+            .then(|| (before.entities().to_vec(), after.entities().to_vec()));
+        let triggers = (before.triggers() != after.triggers())
+            .then(|| (before.triggers().to_vec(), after.triggers().to_vec()));
+
+        Self {
+            size_before: (before.width(), before.height()),
+            size_after: (after.width(), after.height()),
+            changes,
+            entities,
+            triggers,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.size_before == self.size_after
+            && self.changes.is_empty()
+            && self.entities.is_none()
+            && self.triggers.is_none()
+    }
+
+    fn apply(&self, map: &mut WorldMap, forward: bool) {
+        let (width, height) = if forward {
+            self.size_after
+        } else {
+            self.size_before
+        };
+        map.resize(width, height);
+
+        for change in &self.changes {
+            let value = if forward { change.after } else { change.before };
+            match value {
+                Some(block_type) => map.insert(change.pos, block_type),
+                None => map.remove(change.pos),
+            };
+        }
+
+        if let Some((before, after)) = &self.entities {
+            let entities = if forward { after } else { before };
+            map.retain_entities(|_| false);
+            for spawn in entities {
+                map.add_entity(*spawn);
+            }
+        }
+
+        if let Some((before, after)) = &self.triggers {
+            let triggers = if forward { after } else { before };
+            map.retain_triggers(|_| false);
+            for trigger in triggers {
+                map.add_trigger(trigger.clone());
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct History {
+    undo_stack: Vec<Edit>,
+    redo_stack: Vec<Edit>,
+}
+
+impl History {
+    pub fn record(&mut self, before: &WorldMap, after: &WorldMap) {
+        let edit = Edit::between(before, after);
+        if edit.is_empty() {
+            return;
+        }
+
+        if self.undo_stack.len() >= MAX_UNDO_STEPS {
+            self.undo_stack.remove(0);
+        }
+        self.undo_stack.push(edit);
+        self.redo_stack.clear();
+    }
+
+    pub fn undo(&mut self, map: &mut WorldMap) -> bool {
+        match self.undo_stack.pop() {
+            Some(edit) => {
+                edit.apply(map, false);
+                self.redo_stack.push(edit);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn redo(&mut self, map: &mut WorldMap) -> bool {
+        match self.redo_stack.pop() {
+            Some(edit) => {
+                edit.apply(map, true);
+                self.undo_stack.push(edit);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Places a block on the top row and records it as one edit
+    fn edit(history: &mut History, map: &mut WorldMap, x: usize) {
+        let before = map.clone();
+        map.insert((x, 0), BlockType::StoneSlabUp);
+        history.record(&before, map);
+    }
+
+    #[test]
+    fn undo_steps_back_through_several_edits() {
+        let (mut history, mut map) = (History::default(), WorldMap::new(10, 10));
+        let empty = map.clone();
+        for x in 0..3 {
+            edit(&mut history, &mut map, x);
+        }
+
+        assert!(history.undo(&mut map));
+        assert_eq!(map.get((2, 0)), None);
+        assert_eq!(map.get((1, 0)), Some(BlockType::StoneSlabUp));
+        assert!(history.undo(&mut map));
+        assert!(history.undo(&mut map));
+        assert!(Edit::between(&empty, &map).is_empty());
+        assert!(!history.undo(&mut map));
+    }
+
+    #[test]
+    fn redo_brings_back_what_undo_took() {
+        let (mut history, mut map) = (History::default(), WorldMap::new(10, 10));
+        edit(&mut history, &mut map, 0);
+        edit(&mut history, &mut map, 1);
+        let edited = map.clone();
+
+        history.undo(&mut map);
+        history.undo(&mut map);
+        assert!(history.redo(&mut map));
+        assert_eq!(map.get((0, 0)), Some(BlockType::StoneSlabUp));
+        assert_eq!(map.get((1, 0)), None);
+        assert!(history.redo(&mut map));
+        assert!(Edit::between(&edited, &map).is_empty());
+        assert!(!history.redo(&mut map));
+    }
+
+    #[test]
+    fn a_new_edit_clears_redo() {
+        let (mut history, mut map) = (History::default(), WorldMap::new(10, 10));
+        edit(&mut history, &mut map, 0);
+        history.undo(&mut map);
+        edit(&mut history, &mut map, 1);
+
+        assert!(!history.redo(&mut map));
+        assert_eq!(map.get((0, 0)), None);
+    }
+
+    #[test]
+    fn edits_that_change_nothing_are_not_kept() {
+        let (mut history, mut map) = (History::default(), WorldMap::new(10, 10));
+        edit(&mut history, &mut map, 0);
+        history.record(&map.clone(), &map);
+
+        assert!(history.undo(&mut map));
+        assert!(!history.undo(&mut map));
+    }
+
+    #[test]
+    fn only_the_latest_edits_up_to_the_limit_can_be_undone() {
+        let (mut history, mut map) = (History::default(), WorldMap::new(MAX_UNDO_STEPS + 10, 1));
+        for x in 0..MAX_UNDO_STEPS + 10 {
+            edit(&mut history, &mut map, x);
+        }
+
+        let mut undone = 0;
+        while history.undo(&mut map) {
+            undone += 1;
+        }
+        assert_eq!(undone, MAX_UNDO_STEPS);
+        assert_eq!(map.get((9, 0)), Some(BlockType::StoneSlabUp));
+        assert_eq!(map.get((10, 0)), None);
+    }
+}
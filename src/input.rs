@@ -0,0 +1,23 @@
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Input {
+    pub left: bool,
+    pub right: bool,
+    pub jump: bool,
+    pub down: bool, // Drops through one-way platforms
+}
+
+impl Input {
+    // One byte per step in replay files
+    pub fn to_bits(self) -> u8 {
+        self.left as u8 | (self.right as u8) << 1 | (self.jump as u8) << 2 | (self.down as u8) << 3
+    }
+
+    pub fn from_bits(bits: u8) -> Self {
+        Self {
+            left: bits & 1 != 0,
+            right: bits & 1 << 1 != 0,
+            jump: bits & 1 << 2 != 0,
+            down: bits & 1 << 3 != 0,
+        }
+    }
+}
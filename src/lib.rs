@@ -1,21 +1,62 @@
+#[cfg(feature = "render")]
 use raylib::prelude::*;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::fs;
 
-pub mod world;
-pub use world::World;
+pub mod rect;
+pub use rect::Rect;
+pub mod map;
+pub use map::WorldMap;
+pub mod map_file;
+pub use map_file::{MapError, MapHeader, load_map, save_map};
 pub mod player;
 pub use player::Player;
+pub mod age;
+pub mod entity;
+pub use entity::{Entity, EntityKind, EntitySpawn};
+pub mod campaign;
 pub mod dialogue;
-pub use dialogue::DialogueSystem;
-
-pub mod shaders;
-
+pub mod history;
+pub mod input;
+pub use input::Input;
+pub mod clock;
+pub mod save;
+pub use clock::Clock;
+pub mod replay;
+pub mod simulation;
+pub use simulation::Simulation;
+pub mod trigger;
+pub use trigger::{Trigger, TriggerAction, TriggerMode, TriggerState};
+pub mod script;
+
+// Everything that needs a window, the simulation above builds and tests without raylib
+#[cfg(feature = "render")]
+pub mod world;
+#[cfg(feature = "render")]
+pub use world::World;
+#[cfg(feature = "render")]
+pub mod dialogue_system;
+#[cfg(feature = "render")]
+pub mod ghost;
+#[cfg(feature = "render")]
+pub use dialogue_system::DialogueSystem;
+#[cfg(feature = "render")]
+pub mod controls;
+#[cfg(feature = "render")]
+pub use controls::{Action, Controls};
+#[cfg(feature = "render")]
+pub mod settings;
+#[cfg(feature = "render")]
+pub use settings::Settings;
+#[cfg(feature = "render")]
+pub mod menu;
+#[cfg(feature = "render")]
+pub use menu::Menu;
+#[cfg(feature = "render")]
 pub mod dust;
+#[cfg(feature = "render")]
+pub mod shaders;
 
-use crate::player::Age;
-
+#[cfg(feature = "render")]
 pub const BG_COLOR: Color = Color {
     r: 29,
     g: 32,
@@ -32,19 +73,35 @@ pub const BASE_HEIGHT: i32 = 416;
 pub const BLOCK_SIZE: i32 = 8;
 
 pub const DEL_SIZE: i32 = 3;
+pub const MAX_UNDO_STEPS: usize = 200;
 pub const TARGET_FPS: u32 = 60;
 
+// Simulation runs at a fixed rate, movement constants are per step
+pub const FIXED_TIMESTEP: f64 = 1.0 / TARGET_FPS as f64;
+pub const MAX_FRAME_TIME: f64 = 0.25;
+
 pub const PLAYER_SCALE: f32 = 1.;
 
 pub const PLAYER_SPEED: f32 = 1.;
 pub const JUMP_SPEED: f32 = 2.5;
 pub const GRAVITY: f32 = 0.15;
+pub const ONE_WAY_SLACK: f32 = 0.5; // Rounding allowed when standing on a one-way tile
+
+pub const MAP_PATH: &str = "map.json";
+pub const AGES_PATH: &str = "ages.json";
+pub const CAMPAIGN_PATH: &str = "campaign.json";
+pub const REPLAY_PATH: &str = "replay.json";
+pub const SETTINGS_PATH: &str = "settings.json";
+pub const DIALOGUE_PATH: &str = "dialogue.json";
+pub const SAVE_PATH: &str = "save.json";
 
 pub const PLAYER_SPRITE_PATH: &str = "src/assets/player.png";
 pub const TILESET_PATH: &str = "src/assets/tileset.png";
 pub const DEVIL_PATH: &str = "src/assets/devil.png";
 pub const BG_PATH: &str = "src/assets/background.png";
 pub const PARTICLE_PATH: &str = "src/assets/particle.png";
+pub const FONT_PATH: &str = "src/assets/tiny.ttf";
+pub const MUSIC_PATH: &str = "src/assets/music2.mp3";
 
 pub const PLAYER_SPRITE_WALK_INIT: u32 = 1;
 pub const PLAYER_SPRITE_WALK_END: u32 = 5;
@@ -53,34 +110,39 @@ pub const PLAYER_SPRITE_SPEED: f64 = 0.15;
 pub const SPRITE_SIZE: f32 = 8.;
 pub const DEVIL_HEIGHT: f32 = SPRITE_SIZE * 3.;
 
+pub const GAMEPAD: i32 = 0;
+pub const GAMEPAD_DEADZONE: f32 = 0.3;
+
 pub const CAMERA_ZOOM: f32 = 6.;
 pub const CAMERA_SPEED: f32 = 0.08;
 
+pub const BUILDER_MIN_ZOOM: f32 = 0.25;
+pub const BUILDER_MAX_ZOOM: f32 = 8.;
+pub const BUILDER_PAN_SPEED: f32 = 8.;
+pub const MAP_RESIZE_STEP: usize = 10;
+
 // Collision box constants
 pub const COLLISION_BOX_OFFSET_X: f32 = SPRITE_SIZE / 4.0;
 pub const COLLISION_BOX_OFFSET_Y: f32 = SPRITE_SIZE / 4.0;
 pub const PLAYER_COLLISION_BOX_WIDTH: f32 = SPRITE_SIZE / 2.;
 
-pub const PLAYER_ELDER_COLLISION_BOX_HEIGHT: f32 = SPRITE_SIZE / 4. * 3.;
-pub const PLAYER_ADULT_COLLISION_BOX_HEIGHT: f32 = SPRITE_SIZE / 6. * 5.3;
-pub const PLAYER_TEEN_COLLISION_BOX_HEIGHT: f32 = SPRITE_SIZE / 4. * 3.;
-pub const PLAYER_CHILD_COLLISION_BOX_HEIGHT: f32 = SPRITE_SIZE / 6. * 3.8;
-pub const PLAYER_BABY_COLLISION_BOX_HEIGHT: f32 = SPRITE_SIZE / 4. * 3.;
-
 pub const PLAYER_SPRITE_DYING_INIT: u32 = 0;
 pub const PLAYER_SPRITE_DYING_END: u32 = 4;
 pub const IDLE_DYING_TRIGGER_TIME: f64 = 1.0;
 
-pub const PLAYER_INITIAL_AGE: Age = Age::Baby;
-
-pub const LIFETIME: f64 = ELDER_TIME_TO_CHANGE + 5.;
-pub const BABY_TIME_TO_CHANGE: f64 = 5.;
-pub const CHILD_TIME_TO_CHANGE: f64 = 10.;
-pub const TEENAGER_TIME_TO_CHANGE: f64 = 15.;
-pub const ADULT_TIME_TO_CHANGE: f64 = 20.;
-pub const ELDER_TIME_TO_CHANGE: f64 = 25.;
 pub const DEATH_ANIMATION_DURATION: f64 = 3.0;
 
+// Earlier lives shown as ghosts
+pub const GHOST_LIMIT: usize = 5;
+pub const GHOST_OPACITY: u8 = 90;
+
+pub const BOULDER_SIZE: f32 = BLOCK_SIZE as f32;
+pub const BOULDER_MIN_STRENGTH: f32 = 0.7; // Teenagers and adults can push
+pub const BOULDER_ROLL_ACCELERATION: f32 = 0.05;
+pub const BOULDER_FRICTION: f32 = 0.8;
+
+pub const SCRIPT_MAX_OPERATIONS: u64 = 100_000; // Per call, a level script running longer is stopped
+
 pub const NUMBER_OF_PARTICLES: u32 = 400;
 pub const PARTICLE_VELOCITY: f32 = 0.2;
 pub const END_SCENE_SIGHT_MULTIPLIER: f32 = 1.5;
@@ -88,10 +150,13 @@ pub const SIGHT_TRANSITION_SPEED_NORMAL: f32 = 2.0;
 pub const SIGHT_TRANSITION_SPEED_END: f32 = 0.5;
 pub const END_SCENE_CAMERA_OFFSET_Y: f32 = -25.0;
 pub const END_SCENE_CAMERA_TRANSITION_SPEED: f32 = 0.02;
+pub const DIALOGUE_REVEAL_SPEED: f64 = 40.0; // Characters per second
 pub const END_BEFORE_DIALOGUE: f64 = 5.;
+pub const CREDITS_SCROLL_SPEED: f32 = 30.0; // Pixels per second
+pub const CREDITS_LINE_HEIGHT: f32 = 32.0;
 
-// Proximity detection
-pub const END_BLOCK_PROXIMITY_THRESHOLD: f32 = 4.0;
+// How far around an old End block its trigger reaches, in tiles
+pub const END_BLOCK_PROXIMITY_THRESHOLD: usize = 4;
 
 // Death sprite row
 pub const DEATH_SPRITE_ROW: f32 = 5.0;
@@ -107,16 +172,25 @@ pub enum BlockType {
     StoneSlabRight,
     StoneSlabUp,
     StoneSlabDown,
-    Slab,
+    Slab, // One-way platform, solid only from above
     Start,
-    StopAging,
-    End,
+    Exit,
+    Lever,       // Opens every gate on the level when touched
+    LeverPulled, // What a lever becomes once used
+    Gate,
+    Cracked, // Floor that gives way once stood on
+    Grave,   // Left where a life ended
 }
 
 impl BlockType {
     pub fn to_sprite_position(&self) -> (f32, f32) {
         match self {
-            Self::Blank | Self::Start | Self::End | Self::StopAging => (1., 1.),
+            Self::Blank
+            | Self::Start
+            | Self::Exit
+            | Self::Lever
+            | Self::LeverPulled
+            | Self::Grave => (1., 1.),
             Self::StoneLeftDown => (0., 2.),
             Self::StoneLeftUp => (0., 0.),
             Self::StoneRightDown => (2., 2.),
@@ -124,135 +198,76 @@ impl BlockType {
             Self::StoneSlabDown => (1., 2.),
             Self::StoneSlabLeft => (2., 1.),
             Self::StoneSlabRight => (2., 1.),
-            Self::StoneSlabUp | Self::Slab => (1., 2.),
+            Self::StoneSlabUp | Self::Slab | Self::Gate | Self::Cracked => (1., 2.),
         }
     }
 
     pub fn is_collidable(&self) -> bool {
-        !matches!(self, Self::Blank | Self::Start | Self::StopAging)
+        !matches!(
+            self,
+            Self::Blank | Self::Start | Self::Exit | Self::Lever | Self::LeverPulled | Self::Grave // A marker, it must not wall off later lives
+        )
     }
 
-    pub fn is_special_zone(&self) -> bool {
-        matches!(self, Self::StopAging | Self::End)
+    // The borders drawn around carved space, recomputed by the builder
+    pub fn is_stone(&self) -> bool {
+        matches!(
+            self,
+            Self::StoneLeftUp
+                | Self::StoneLeftDown
+                | Self::StoneRightUp
+                | Self::StoneRightDown
+                | Self::StoneSlabLeft
+                | Self::StoneSlabRight
+                | Self::StoneSlabUp
+                | Self::StoneSlabDown
+        )
     }
-}
-
-#[derive(Serialize, Deserialize)]
-struct SerializableMap {
-    #[serde(with = "tuple_vec_map")]
-    blocks: HashMap<(usize, usize), BlockType>,
-}
-
-mod tuple_vec_map {
-    use super::BlockType;
-    use serde::{Deserialize, Deserializer, Serialize, Serializer};
-    use std::collections::HashMap;
 
-    #[derive(Serialize, Deserialize)]
-    struct Entry {
-        x: usize,
-        y: usize,
-        block_type: BlockType,
+    pub fn is_one_way(&self) -> bool {
+        matches!(self, Self::Slab)
     }
 
-    pub fn serialize<S>(
-        map: &HashMap<(usize, usize), BlockType>,
-        serializer: S,
-    ) -> Result<S::Ok, S::Error>
-    where
-        S: Serializer,
-    {
-        let entries: Vec<Entry> = map
-            .iter()
-            .map(|(&(x, y), &block_type)| Entry { x, y, block_type })
-            .collect();
-        entries.serialize(serializer)
+    // One-way tiles only stop what stood above their top before moving down,
+    // `previous_bottom` is None for any other move
+    pub fn stops(&self, top: f32, previous_bottom: Option<f32>) -> bool {
+        self.is_collidable()
+            && (!self.is_one_way()
+                || previous_bottom.is_some_and(|bottom| bottom <= top + ONE_WAY_SLACK))
     }
 
-    pub fn deserialize<'de, D>(
-        deserializer: D,
-    ) -> Result<HashMap<(usize, usize), BlockType>, D::Error>
-    where
-        D: Deserializer<'de>,
-    {
-        let entries = Vec::<Entry>::deserialize(deserializer)?;
-        Ok(entries
-            .into_iter()
-            .map(|e| ((e.x, e.y), e.block_type))
-            .collect())
+    pub fn is_special_zone(&self) -> bool {
+        matches!(self, Self::Exit)
     }
 }
 
-pub type WorldMap = HashMap<(usize, usize), BlockType>;
-
-pub fn load_map() -> WorldMap {
-    match fs::read_to_string("map.json") {
-        Ok(content) => match serde_json::from_str::<SerializableMap>(&content) {
-            Ok(serializable_map) => {
-                println!(
-                    "Loaded map.json with {} blocks",
-                    serializable_map.blocks.len()
-                );
-                serializable_map.blocks
-            }
-            Err(e) => {
-                eprintln!("Failed to parse map.json: {}", e);
-                HashMap::new()
-            }
-        },
-        Err(_) => {
-            println!("No map.json found, starting empty.");
-            HashMap::new()
-        }
-    }
-}
+pub fn recompute_stone_borders(map: &mut WorldMap) {
+    // Exits, levers and the like placed by the designer stay where they are
+    map.retain(|_, bt| !bt.is_stone());
 
-pub fn save_map(map: &WorldMap) {
-    let serializable_map = SerializableMap {
-        blocks: map.clone(),
+    let (width, height) = (map.width(), map.height());
+    // Only carved space gets a border, a grave is dug where carved space was
+    let is_blank = |map: &WorldMap, x: usize, y: usize| {
+        matches!(map.get((x, y)), Some(BlockType::Blank | BlockType::Grave))
     };
 
-    match serde_json::to_string_pretty(&serializable_map) {
-        Ok(json) => {
-            if let Err(e) = fs::write("map.json", json) {
-                eprintln!("Failed to save map: {}", e);
-            } else {
-                println!("Map saved to map.json with {} blocks", map.len());
-            }
-        }
-        Err(e) => eprintln!("Failed to serialize map: {}", e),
-    }
-}
-
-pub fn recompute_stone_borders(map: &mut WorldMap) {
-    map.retain(|_, bt| *bt == BlockType::Blank || *bt == BlockType::Start);
-
-    for y in 0..GRID_HEIGHT {
-        for x in 0..GRID_WIDTH {
+    for y in 0..height {
+        for x in 0..width {
             let pos = (x, y);
 
-            if map.contains_key(&pos) {
+            if map.contains(pos) {
                 continue;
             }
 
-            let up = y > 0 && matches!(map.get(&(x, y - 1)), Some(BlockType::Blank));
-            let down =
-                y < GRID_HEIGHT - 1 && matches!(map.get(&(x, y + 1)), Some(BlockType::Blank));
-            let left = x > 0 && matches!(map.get(&(x - 1, y)), Some(BlockType::Blank));
-            let right =
-                x < GRID_WIDTH - 1 && matches!(map.get(&(x + 1, y)), Some(BlockType::Blank));
-
-            let up_left =
-                x > 0 && y > 0 && matches!(map.get(&(x - 1, y - 1)), Some(BlockType::Blank));
-            let up_right = x < GRID_WIDTH - 1
-                && y > 0
-                && matches!(map.get(&(x + 1, y - 1)), Some(BlockType::Blank));
-            let down_left = x > 0
-                && y < GRID_HEIGHT - 1
-                && matches!(map.get(&(x - 1, y + 1)), Some(BlockType::Blank));
-            let down_right = x < GRID_WIDTH - 1
-                && y < GRID_HEIGHT - 1
-                && matches!(map.get(&(x + 1, y + 1)), Some(BlockType::Blank));
+            let up = y > 0 && is_blank(map, x, y - 1);
+            let down = y < height - 1 && is_blank(map, x, y + 1);
+            let left = x > 0 && is_blank(map, x - 1, y);
+            let right = x < width - 1 && is_blank(map, x + 1, y);
+
+            let up_left = x > 0 && y > 0 && is_blank(map, x - 1, y - 1);
+            let up_right = x < width - 1 && y > 0 && is_blank(map, x + 1, y - 1);
+            let down_left = x > 0 && y < height - 1 && is_blank(map, x - 1, y + 1);
+            let down_right = x < width - 1 && y < height - 1 && is_blank(map, x + 1, y + 1);
 
             let border_type = if up_left && !up && !left {
                 Some(BlockType::StoneRightDown)
@@ -281,6 +296,17 @@ pub fn recompute_stone_borders(map: &mut WorldMap) {
     }
 }
 
+// World space rectangle seen through a camera on the base resolution
+#[cfg(feature = "render")]
+pub fn visible_area(camera: &Camera2D) -> Rect {
+    Rect {
+        x: camera.target.x - camera.offset.x / camera.zoom,
+        y: camera.target.y - camera.offset.y / camera.zoom,
+        width: BASE_WIDTH as f32 / camera.zoom,
+        height: BASE_HEIGHT as f32 / camera.zoom,
+    }
+}
+
 pub fn smoothing(a: f32, b: f32, s: f32) -> f32 {
     a + (b - a) * s
 }
@@ -0,0 +1,243 @@
+use crate::*;
+
+#[derive(Clone, Debug)]
+pub struct WorldMap {
+    width: usize,
+    height: usize,
+    cells: Vec<Option<BlockType>>,
+    entities: Vec<EntitySpawn>,
+    triggers: Vec<Trigger>,
+}
+
+impl Default for WorldMap {
+    fn default() -> Self {
+        Self::new(GRID_WIDTH, GRID_HEIGHT)
+    }
+}
+
+impl WorldMap {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            cells: vec![None; width * height],
+            entities: Vec::new(),
+            triggers: Vec::new(),
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn in_bounds(&self, (x, y): (usize, usize)) -> bool {
+        x < self.width && y < self.height
+    }
+
+    fn index(&self, (x, y): (usize, usize)) -> Option<usize> {
+        self.in_bounds((x, y)).then_some(y * self.width + x)
+    }
+
+    pub fn get(&self, pos: (usize, usize)) -> Option<BlockType> {
+        self.index(pos).and_then(|i| self.cells[i])
+    }
+
+    pub fn contains(&self, pos: (usize, usize)) -> bool {
+        self.get(pos).is_some()
+    }
+
+    // Out of bounds positions are ignored
+    pub fn insert(&mut self, pos: (usize, usize), block_type: BlockType) -> Option<BlockType> {
+        let i = self.index(pos)?;
+        self.cells[i].replace(block_type)
+    }
+
+    pub fn remove(&mut self, pos: (usize, usize)) -> Option<BlockType> {
+        let i = self.index(pos)?;
+        self.cells[i].take()
+    }
+
+    pub fn retain(&mut self, mut f: impl FnMut((usize, usize), BlockType) -> bool) {
+        let width = self.width;
+        for (i, cell) in self.cells.iter_mut().enumerate() {
+            if let Some(block_type) = *cell
+                && !f((i % width, i / width), block_type)
+            {
+                *cell = None;
+            }
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = ((usize, usize), BlockType)> + '_ {
+        let width = self.width;
+        self.cells
+            .iter()
+            .enumerate()
+            .filter_map(move |(i, cell)| cell.map(|bt| ((i % width, i / width), bt)))
+    }
+
+    pub fn len(&self) -> usize {
+        self.cells.iter().filter(|cell| cell.is_some()).count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cells.iter().all(|cell| cell.is_none())
+    }
+
+    pub fn find(&self, block_type: BlockType) -> Option<(usize, usize)> {
+        self.iter()
+            .find(|(_, bt)| *bt == block_type)
+            .map(|(pos, _)| pos)
+    }
+
+    pub fn tile_at(x: f32, y: f32) -> (i32, i32) {
+        (
+            (x / BLOCK_SIZE as f32).floor() as i32,
+            (y / BLOCK_SIZE as f32).floor() as i32,
+        )
+    }
+
+    // Keeps the overlapping cells, anything outside the new size is dropped
+    pub fn resize(&mut self, width: usize, height: usize) {
+        if (width, height) == (self.width, self.height) {
+            return;
+        }
+
+        let mut resized = Self::new(width, height);
+        for (pos, block_type) in self.iter() {
+            resized.insert(pos, block_type);
+        }
+        for spawn in &self.entities {
+            resized.add_entity(*spawn);
+        }
+        for trigger in &self.triggers {
+            resized.add_trigger(trigger.clone());
+        }
+        *self = resized;
+    }
+
+    pub fn entities(&self) -> &[EntitySpawn] {
+        &self.entities
+    }
+
+    // Out of bounds entities are ignored like blocks
+    pub fn add_entity(&mut self, spawn: EntitySpawn) {
+        if self.in_bounds((spawn.x, spawn.y)) {
+            self.entities.push(spawn);
+        }
+    }
+
+    pub fn retain_entities(&mut self, f: impl FnMut(&EntitySpawn) -> bool) {
+        self.entities.retain(f);
+    }
+
+    pub fn triggers(&self) -> &[Trigger] {
+        &self.triggers
+    }
+
+    pub fn triggers_mut(&mut self) -> &mut [Trigger] {
+        &mut self.triggers
+    }
+
+    // Cut down to the grid, triggers starting outside it are ignored
+    pub fn add_trigger(&mut self, mut trigger: Trigger) {
+        if self.in_bounds((trigger.x, trigger.y)) {
+            trigger.width = trigger.width.min(self.width - trigger.x);
+            trigger.height = trigger.height.min(self.height - trigger.y);
+            self.triggers.push(trigger);
+        }
+    }
+
+    pub fn retain_triggers(&mut self, f: impl FnMut(&Trigger) -> bool) {
+        self.triggers.retain(f);
+    }
+
+    pub fn remove_trigger(&mut self, index: usize) -> Option<Trigger> {
+        (index < self.triggers.len()).then(|| self.triggers.remove(index))
+    }
+
+    // In bounds tiles touched by a world space rectangle, grown by `margin` tiles
+    pub fn tiles_in(
+        &self,
+        rect: &Rect,
+        margin: usize,
+    ) -> impl Iterator<Item = (usize, usize)> + use<> {
+        let margin = margin as i32;
+        let (min_x, min_y) = Self::tile_at(rect.x, rect.y);
+        let (max_x, max_y) = Self::tile_at(rect.x + rect.width, rect.y + rect.height);
+
+        let clamp_x = |x: i32| x.clamp(0, self.width as i32) as usize;
+        let clamp_y = |y: i32| y.clamp(0, self.height as i32) as usize;
+        let (x0, x1) = (clamp_x(min_x - margin), clamp_x(max_x + margin + 1));
+        let (y0, y1) = (clamp_y(min_y - margin), clamp_y(max_y + margin + 1));
+
+        (y0..y1).flat_map(move |y| (x0..x1).map(move |x| (x, y)))
+    }
+
+    pub fn blocks_in(
+        &self,
+        rect: &Rect,
+        margin: usize,
+    ) -> impl Iterator<Item = ((usize, usize), BlockType)> + '_ {
+        self.tiles_in(rect, margin)
+            .filter_map(|pos| self.get(pos).map(|bt| (pos, bt)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tiles_past_the_edges_are_left_out() {
+        let map = WorldMap::new(4, 3);
+        let size = BLOCK_SIZE as f32;
+
+        // Hangs over the top left corner
+        let corner = Rect::new(-size * 1.5, -size * 1.5, size, size);
+        assert_eq!(map.tiles_in(&corner, 1).collect::<Vec<_>>(), [(0, 0)]);
+
+        // Hangs over the bottom right corner
+        let corner = Rect::new(size * 3.5, size * 2.5, size * 2.0, size * 2.0);
+        assert_eq!(map.tiles_in(&corner, 0).collect::<Vec<_>>(), [(3, 2)]);
+
+        // Covers all of it and more
+        let around = Rect::new(-size * 10.0, -size * 10.0, size * 30.0, size * 30.0);
+        assert_eq!(map.tiles_in(&around, 2).count(), 4 * 3);
+
+        // Entirely outside
+        let outside = Rect::new(size * 10.0, 0.0, size, size);
+        assert_eq!(map.tiles_in(&outside, 1).count(), 0);
+    }
+
+    #[test]
+    fn margin_grows_the_area_on_every_side() {
+        let map = WorldMap::new(10, 10);
+        let size = BLOCK_SIZE as f32;
+        let tile = Rect::new(size * 4.25, size * 4.25, size * 0.5, size * 0.5);
+
+        assert_eq!(map.tiles_in(&tile, 0).collect::<Vec<_>>(), [(4, 4)]);
+        let grown: Vec<_> = map.tiles_in(&tile, 1).collect();
+        assert_eq!(grown.len(), 9);
+        assert_eq!((grown[0], grown[8]), ((3, 3), (5, 5)));
+    }
+
+    #[test]
+    fn blocks_in_skips_empty_cells() {
+        let mut map = WorldMap::new(5, 5);
+        map.insert((1, 1), BlockType::StoneSlabUp);
+        map.insert((3, 3), BlockType::StoneSlabUp);
+        let size = BLOCK_SIZE as f32;
+
+        let area = Rect::new(0.0, 0.0, size * 2.5, size * 2.5);
+        assert_eq!(
+            map.blocks_in(&area, 0).collect::<Vec<_>>(),
+            [((1, 1), BlockType::StoneSlabUp)]
+        );
+        assert_eq!(map.blocks_in(&area, 1).count(), 2);
+    }
+}
@@ -0,0 +1,378 @@
+use crate::*;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::{error::Error, fmt, fs, io};
+
+pub const MAP_VERSION: u32 = 4;
+
+#[derive(Debug)]
+pub enum MapError {
+    Io(io::Error),
+    Parse(serde_json::Error),
+    UnsupportedVersion(u32),
+    OutOfBounds { x: usize, y: usize },
+}
+
+impl fmt::Display for MapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{}", e),
+            Self::Parse(e) => write!(f, "invalid map file: {}", e),
+            Self::UnsupportedVersion(v) => write!(
+                f,
+                "unsupported map version {} (expected 1..={})",
+                v, MAP_VERSION
+            ),
+            Self::OutOfBounds { x, y } => write!(f, "block at ({}, {}) is outside the grid", x, y),
+        }
+    }
+}
+
+impl Error for MapError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            Self::Parse(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for MapError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for MapError {
+    fn from(e: serde_json::Error) -> Self {
+        Self::Parse(e)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct MapHeader {
+    pub name: String,
+    #[serde(default)]
+    pub author: String,
+    pub width: usize,
+    pub height: usize,
+    #[serde(default)]
+    pub spawn: Option<(usize, usize)>,
+}
+
+impl Default for MapHeader {
+    fn default() -> Self {
+        Self {
+            name: "Untitled".to_string(),
+            author: String::new(),
+            width: GRID_WIDTH,
+            height: GRID_HEIGHT,
+            spawn: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct BlockEntry {
+    x: usize,
+    y: usize,
+    block_type: BlockType,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct MapFile {
+    version: u32,
+    header: MapHeader,
+    blocks: Vec<BlockEntry>,
+    #[serde(default)]
+    entities: Vec<EntitySpawn>,
+    #[serde(default)]
+    triggers: Vec<Trigger>,
+}
+
+impl MapFile {
+    pub fn new(header: &MapHeader, map: &WorldMap) -> Self {
+        Self {
+            version: MAP_VERSION,
+            header: MapHeader {
+                width: map.width(),
+                height: map.height(),
+                spawn: map.find(BlockType::Start),
+                ..header.clone()
+            },
+            blocks: map
+                .iter()
+                .map(|((x, y), block_type)| BlockEntry { x, y, block_type })
+                .collect(),
+            entities: map.entities().to_vec(),
+            triggers: map.triggers().to_vec(),
+        }
+    }
+
+    pub fn into_map(self) -> Result<(MapHeader, WorldMap), MapError> {
+        let mut map = WorldMap::new(self.header.width, self.header.height);
+        for BlockEntry { x, y, block_type } in self.blocks {
+            if !map.in_bounds((x, y)) {
+                return Err(MapError::OutOfBounds { x, y });
+            }
+            map.insert((x, y), block_type);
+        }
+        for spawn in self.entities {
+            if !map.in_bounds((spawn.x, spawn.y)) {
+                return Err(MapError::OutOfBounds {
+                    x: spawn.x,
+                    y: spawn.y,
+                });
+            }
+            map.add_entity(spawn);
+        }
+        for trigger in self.triggers {
+            if !map.in_bounds((trigger.x, trigger.y)) {
+                return Err(MapError::OutOfBounds {
+                    x: trigger.x,
+                    y: trigger.y,
+                });
+            }
+            map.add_trigger(trigger);
+        }
+        Ok((self.header, map))
+    }
+}
+
+pub fn load_map(path: &str) -> Result<(MapHeader, WorldMap), MapError> {
+    let content = fs::read_to_string(path)?;
+    let value = migrate(serde_json::from_str(&content)?)?;
+    let file: MapFile = serde_json::from_value(value)?;
+    let (header, map) = file.into_map()?;
+
+    println!("Loaded {} with {} blocks", path, map.len());
+    Ok((header, map))
+}
+
+pub fn save_map(path: &str, header: &MapHeader, map: &WorldMap) -> Result<(), MapError> {
+    let file = MapFile::new(header, map);
+    fs::write(path, serde_json::to_string_pretty(&file)?)?;
+    println!("Map saved to {} with {} blocks", path, map.len());
+    Ok(())
+}
+
+// Upgrades a parsed map file one version at a time until it is current
+fn migrate(mut value: Value) -> Result<Value, MapError> {
+    loop {
+        let version = value.get("version").and_then(Value::as_u64).unwrap_or(1) as u32;
+        value = match version {
+            1 => migrate_v1(value)?,
+            2 => migrate_v2(value)?,
+            3 => migrate_v3(value)?,
+            MAP_VERSION => return Ok(value),
+            v => return Err(MapError::UnsupportedVersion(v)),
+        };
+    }
+}
+
+// Block names as they were written, old files may use types that are gone now
+#[derive(Serialize, Deserialize)]
+struct RawBlock {
+    x: usize,
+    y: usize,
+    block_type: String,
+}
+
+// Version 1 files were a bare block list sized to the builder window
+fn migrate_v1(value: Value) -> Result<Value, MapError> {
+    let blocks: Vec<RawBlock> = serde_json::from_value(value["blocks"].clone())?;
+
+    let width = blocks.iter().map(|b| b.x + 1).fold(GRID_WIDTH, usize::max);
+    let height = blocks.iter().map(|b| b.y + 1).fold(GRID_HEIGHT, usize::max);
+    let spawn = blocks
+        .iter()
+        .find(|b| b.block_type == "Start")
+        .map(|b| (b.x, b.y));
+    let header = MapHeader {
+        width,
+        height,
+        spawn,
+        ..MapHeader::default()
+    };
+
+    Ok(json!({
+        "version": 2,
+        "header": header,
+        "blocks": blocks,
+    }))
+}
+
+// Version 2 kept the devil as an End block and boulders as Boulder blocks
+fn migrate_v2(value: Value) -> Result<Value, MapError> {
+    let blocks: Vec<RawBlock> = serde_json::from_value(value["blocks"].clone())?;
+
+    let mut entities = Vec::new();
+    let blocks: Vec<RawBlock> = blocks
+        .into_iter()
+        .map(|block| {
+            let kind = match block.block_type.as_str() {
+                "End" => Some(EntityKind::Devil),
+                "Boulder" => Some(EntityKind::Boulder),
+                _ => None,
+            };
+            match kind {
+                Some(kind) => {
+                    entities.push(EntitySpawn {
+                        kind,
+                        x: block.x,
+                        y: block.y,
+                    });
+                    RawBlock {
+                        block_type: "Blank".to_string(),
+                        ..block
+                    }
+                }
+                None => block,
+            }
+        })
+        .collect();
+
+    Ok(json!({
+        "version": 3,
+        "header": value["header"],
+        "blocks": blocks,
+        "entities": entities,
+    }))
+}
+
+// Version 3 stopped aging with StopAging blocks and ended the level near the devil
+fn migrate_v3(value: Value) -> Result<Value, MapError> {
+    let blocks: Vec<RawBlock> = serde_json::from_value(value["blocks"].clone())?;
+    let entities: Vec<EntitySpawn> = serde_json::from_value(value["entities"].clone())?;
+
+    let mut triggers = Vec::new();
+    let blocks: Vec<RawBlock> = blocks
+        .into_iter()
+        .map(|block| {
+            if block.block_type != "StopAging" {
+                return block;
+            }
+            let mut trigger = Trigger::between(
+                (block.x, block.y),
+                (block.x, block.y),
+                TriggerAction::StopAging,
+            );
+            trigger.mode = TriggerMode::Repeat;
+            triggers.push(trigger);
+            RawBlock {
+                block_type: "Blank".to_string(),
+                ..block
+            }
+        })
+        .collect();
+
+    // The devil was reached within a few tiles of the tile it stands on
+    let reach = END_BLOCK_PROXIMITY_THRESHOLD;
+    for devil in entities.iter().filter(|e| e.kind == EntityKind::Devil) {
+        let mut trigger = Trigger::between(
+            (devil.x.saturating_sub(reach), devil.y.saturating_sub(reach)),
+            (devil.x + reach, devil.y + reach),
+            TriggerAction::EndScene,
+        );
+        trigger.mode = TriggerMode::Repeat;
+        triggers.push(trigger);
+    }
+
+    Ok(json!({
+        "version": 4,
+        "header": value["header"],
+        "blocks": blocks,
+        "entities": entities,
+        "triggers": triggers,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(value: Value) -> Result<(MapHeader, WorldMap), MapError> {
+        let file: MapFile = serde_json::from_value(migrate(value)?)?;
+        file.into_map()
+    }
+
+    #[test]
+    fn version_1_map_migrates_through_every_version() {
+        let v1 = json!({
+            "blocks": [
+                { "x": 1, "y": 1, "block_type": "Start" },
+                { "x": 3, "y": 3, "block_type": "StopAging" },
+                { "x": 6, "y": 4, "block_type": "Boulder" },
+                { "x": 10, "y": 10, "block_type": "End" },
+                { "x": 120, "y": 2, "block_type": "StoneSlabUp" },
+            ]
+        });
+        let (header, map) = load(v1).unwrap();
+
+        // Grown to fit the blocks, but never smaller than the old window
+        assert_eq!((header.width, header.height), (121, GRID_HEIGHT));
+        assert_eq!(header.spawn, Some((1, 1)));
+        assert_eq!(map.get((120, 2)), Some(BlockType::StoneSlabUp));
+
+        // The devil, boulders and aging blocks left blank tiles behind
+        for pos in [(3, 3), (6, 4), (10, 10)] {
+            assert_eq!(map.get(pos), Some(BlockType::Blank));
+        }
+        let kinds: Vec<_> = map.entities().iter().map(|e| (e.kind, e.x, e.y)).collect();
+        assert_eq!(
+            kinds,
+            vec![(EntityKind::Boulder, 6, 4), (EntityKind::Devil, 10, 10)]
+        );
+
+        let reach = END_BLOCK_PROXIMITY_THRESHOLD;
+        let triggers = map.triggers();
+        assert_eq!(triggers.len(), 2);
+        assert_eq!(triggers[0].action, TriggerAction::StopAging);
+        assert_eq!(triggers[0].mode, TriggerMode::Repeat);
+        assert_eq!(
+            (
+                triggers[0].x,
+                triggers[0].y,
+                triggers[0].width,
+                triggers[0].height
+            ),
+            (3, 3, 1, 1)
+        );
+        assert_eq!(triggers[1].action, TriggerAction::EndScene);
+        assert_eq!(triggers[1].mode, TriggerMode::Repeat);
+        assert_eq!((triggers[1].x, triggers[1].y), (10 - reach, 10 - reach));
+        assert_eq!(triggers[1].width, 2 * reach + 1);
+    }
+
+    #[test]
+    fn current_map_round_trips_unchanged() {
+        let mut map = WorldMap::new(8, 6);
+        map.insert((1, 4), BlockType::Start);
+        map.insert((2, 4), BlockType::Slab);
+        map.add_trigger(Trigger::between(
+            (0, 0),
+            (2, 1),
+            TriggerAction::SetFlag("seen".to_string()),
+        ));
+        let value = serde_json::to_value(MapFile::new(&MapHeader::default(), &map)).unwrap();
+
+        let (header, loaded) = load(value).unwrap();
+        assert_eq!(header.spawn, Some((1, 4)));
+        assert_eq!(loaded.get((2, 4)), Some(BlockType::Slab));
+        assert_eq!(loaded.triggers(), map.triggers());
+    }
+
+    #[test]
+    fn newer_version_is_refused() {
+        let value = json!({ "version": MAP_VERSION + 1 });
+        assert!(matches!(
+            migrate(value),
+            Err(MapError::UnsupportedVersion(v)) if v == MAP_VERSION + 1
+        ));
+        assert_eq!(
+            MapError::UnsupportedVersion(0).to_string(),
+            format!("unsupported map version 0 (expected 1..={})", MAP_VERSION)
+        );
+    }
+}
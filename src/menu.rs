@@ -0,0 +1,93 @@
+use crate::*;
+
+// A vertical list of options picked with MenuUp/MenuDown and Confirm
+pub struct Menu {
+    pub title: String,
+    pub items: Vec<String>,
+    pub selected: usize,
+}
+
+impl Menu {
+    pub fn new(title: &str, items: &[&str]) -> Self {
+        Self {
+            title: title.to_string(),
+            items: items.iter().map(|item| item.to_string()).collect(),
+            selected: 0,
+        }
+    }
+
+    // Index of the item confirmed this frame
+    pub fn handle_input(&mut self, rl: &RaylibHandle, controls: &Controls) -> Option<usize> {
+        if controls.is_pressed(rl, Action::MenuUp) {
+            self.selected = self.selected.saturating_sub(1);
+        } else if controls.is_pressed(rl, Action::MenuDown) {
+            self.selected = (self.selected + 1).min(self.items.len() - 1);
+        } else if controls.is_pressed(rl, Action::Confirm) {
+            return Some(self.selected);
+        }
+        None
+    }
+
+    pub fn draw<D: RaylibDraw>(
+        &self,
+        d: &mut D,
+        font: &Font,
+        screen_width: i32,
+        screen_height: i32,
+    ) {
+        let base_dim = screen_width.min(screen_height) as f32;
+        let font_size = (base_dim * 0.045).clamp(16.0, 32.0);
+        let title_size = font_size * 2.0;
+        let line_spacing = font_size * 0.5;
+
+        let selected_color = Color::new(251, 73, 52, 255);
+        let color = Color::new(235, 219, 178, 255);
+
+        // Title and items centered as one block
+        let total_height =
+            title_size + line_spacing * 2.0 + self.items.len() as f32 * (font_size + line_spacing);
+        let mut y_pos = (screen_height as f32 - total_height) / 2.0;
+
+        draw_centered(
+            d,
+            font,
+            &self.title,
+            y_pos,
+            title_size,
+            selected_color,
+            screen_width,
+        );
+        y_pos += title_size + line_spacing * 2.0;
+
+        for (i, item) in self.items.iter().enumerate() {
+            let (text, color) = if i == self.selected {
+                (format!("> {} <", item), selected_color)
+            } else {
+                (item.clone(), color)
+            };
+            draw_centered(d, font, &text, y_pos, font_size, color, screen_width);
+            y_pos += font_size + line_spacing;
+        }
+    }
+}
+
+pub fn draw_centered<D: RaylibDraw>(
+    d: &mut D,
+    font: &Font,
+    text: &str,
+    y_pos: f32,
+    font_size: f32,
+    color: Color,
+    screen_width: i32,
+) {
+    let spacing = font_size * 0.1;
+    let width = measure_text_ex(font, text, font_size, spacing).x;
+    d.draw_text_ex(
+        font,
+        text,
+        Vector2::new((screen_width as f32 - width) / 2.0, y_pos),
+        font_size,
+        spacing,
+        color,
+    );
+}
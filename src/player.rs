@@ -1,78 +1,11 @@
+use crate::age::{Age, AgeStage, LifeCycle};
 use crate::*;
-use raylib::prelude::*;
-use std::error::Error;
-
-pub struct AgeAttributes {
-    pub sight: f32,
-    pub strength: f32,
-    pub speed: f32,
-    pub jump_cooldown: f32,
-}
-
-#[derive(Debug, Clone)]
-pub enum Age {
-    Baby,
-    Child,
-    Teenager,
-    Adult,
-    Elder,
-}
-
-impl Age {
-    pub fn to_value(&self) -> f32 {
-        match self {
-            Self::Baby => 0.0,
-            Self::Child => 1.0,
-            Self::Teenager => 2.0,
-            Self::Adult => 3.0,
-            Self::Elder => 4.0,
-        }
-    }
-
-    pub fn collision_box_height(&self) -> f32 {
-        match self {
-            Self::Baby => PLAYER_BABY_COLLISION_BOX_HEIGHT,
-            Self::Child => PLAYER_CHILD_COLLISION_BOX_HEIGHT,
-            Self::Teenager => PLAYER_TEEN_COLLISION_BOX_HEIGHT,
-            Self::Adult => PLAYER_ADULT_COLLISION_BOX_HEIGHT,
-            Self::Elder => PLAYER_ELDER_COLLISION_BOX_HEIGHT,
-        }
-    }
 
-    pub fn attributes(&self) -> AgeAttributes {
-        match self {
-            Self::Baby => AgeAttributes {
-                sight: 0.5,
-                strength: 0.2,
-                speed: 0.3,
-                jump_cooldown: 2.0,
-            },
-            Self::Child => AgeAttributes {
-                sight: 0.7,
-                strength: 0.4,
-                speed: 0.6,
-                jump_cooldown: 1.5,
-            },
-            Self::Teenager => AgeAttributes {
-                sight: 1.0,
-                strength: 0.7,
-                speed: 0.9,
-                jump_cooldown: 0.8,
-            },
-            Self::Adult => AgeAttributes {
-                sight: 1.0,
-                strength: 1.0,
-                speed: 1.0,
-                jump_cooldown: 0.5,
-            },
-            Self::Elder => AgeAttributes {
-                sight: 0.6,
-                strength: 0.5,
-                speed: 0.4,
-                jump_cooldown: 2.5,
-            },
-        }
-    }
+#[derive(Clone, Debug)]
+pub enum AgeEvent {
+    Aged(Age),
+    Died,
+    Reborn,
 }
 
 #[derive(Clone, Debug)]
@@ -99,28 +32,27 @@ pub enum PlayerState {
 }
 
 impl PlayerState {
-    pub fn increment_count(&mut self, game_handle: &mut RaylibHandle) -> bool {
-        let current_time = game_handle.get_time();
+    pub fn increment_count(&mut self, current_time: f64) -> bool {
         let mut frame_advanced = false;
 
         match self {
-            PlayerState::Walk { count, last_update } | PlayerState::Jump { count, last_update } => {
-                if current_time - *last_update > PLAYER_SPRITE_SPEED {
-                    *count = if *count < PLAYER_SPRITE_WALK_END {
-                        *count + 1
-                    } else {
-                        PLAYER_SPRITE_WALK_INIT
-                    };
-                    *last_update = current_time;
-                    frame_advanced = true;
-                }
+            PlayerState::Walk { count, last_update } | PlayerState::Jump { count, last_update }
+                if current_time - *last_update > PLAYER_SPRITE_SPEED =>
+            {
+                *count = if *count < PLAYER_SPRITE_WALK_END {
+                    *count + 1
+                } else {
+                    PLAYER_SPRITE_WALK_INIT
+                };
+                *last_update = current_time;
+                frame_advanced = true;
             }
-            PlayerState::Death { count, last_update } => {
-                if current_time - *last_update > PLAYER_SPRITE_SPEED && *count < 4 {
-                    *count += 1;
-                    *last_update = current_time;
-                    frame_advanced = true;
-                }
+            PlayerState::Death { count, last_update }
+                if current_time - *last_update > PLAYER_SPRITE_SPEED && *count < 4 =>
+            {
+                *count += 1;
+                *last_update = current_time;
+                frame_advanced = true;
             }
             _ => {}
         }
@@ -129,14 +61,18 @@ impl PlayerState {
 }
 
 pub struct Player {
-    pub body: Rectangle,
-    pub collision_box: Rectangle,
+    pub body: Rect,
+    pub previous_position: (f32, f32),
+    pub collision_box: Rect,
     pub vel: (f32, f32),
     pub state: PlayerState,
-    pub sprite: Texture2D,
     pub grounded: bool,
     pub facing: Facing,
+    pub life_cycle: LifeCycle,
     pub age: Age,
+    pub age_timer: f64, // Seconds lived in the current life
+    pub age_events: Vec<AgeEvent>,
+    pub lives: u32, // Lives lived so far, counting the current one
     pub current_sight: f32,
     pub target_sight: f32,
     pub last_jump_time: f64,
@@ -144,37 +80,39 @@ pub struct Player {
     pub death_start_time: f64,
     pub spawn_position: (f32, f32),
     pub can_age: bool,
-    pub end_scene_active: bool, // For StopAging
-    pub end_triggered: bool,    // For End block proximity
+    pub end_scene_active: bool, // Set once aging is stopped
+    pub end_triggered: bool,    // Close enough to the devil
+    pub exit_reached: bool,     // For Exit blocks
 }
 
 impl Player {
-    pub fn new(
-        game_handle: &mut RaylibHandle,
-        game_thread: &RaylibThread,
-        x: f32,
-        y: f32,
-    ) -> Result<Self, Box<dyn Error>> {
-        let initial_sight = PLAYER_INITIAL_AGE.attributes().sight;
-        Ok(Self {
-            body: Rectangle {
+    pub fn new(x: f32, y: f32, life_cycle: LifeCycle) -> Self {
+        let initial_stage = life_cycle.stage(Age::default());
+        let initial_sight = initial_stage.sight;
+        let initial_height = initial_stage.collision_box_height;
+        Self {
+            body: Rect {
                 x,
                 y,
                 width: PLAYER_SCALE * SPRITE_SIZE,
                 height: PLAYER_SCALE * SPRITE_SIZE,
             },
-            collision_box: Rectangle {
+            previous_position: (x, y),
+            collision_box: Rect {
                 x: x + SPRITE_SIZE / 4.0,
                 y: y + SPRITE_SIZE / 4.0,
                 width: PLAYER_SCALE * PLAYER_COLLISION_BOX_WIDTH,
-                height: PLAYER_SCALE * PLAYER_INITIAL_AGE.collision_box_height(),
+                height: PLAYER_SCALE * initial_height,
             },
             state: PlayerState::Idle,
-            sprite: game_handle.load_texture(game_thread, PLAYER_SPRITE_PATH)?,
             grounded: true,
             vel: (0.0, 0.0),
             facing: Facing::Right,
-            age: PLAYER_INITIAL_AGE,
+            life_cycle,
+            age: Age::default(),
+            age_timer: 0.0,
+            age_events: Vec::new(),
+            lives: 1,
             current_sight: initial_sight,
             target_sight: initial_sight,
             last_jump_time: 0.0,
@@ -184,12 +122,17 @@ impl Player {
             can_age: true,
             end_scene_active: false,
             end_triggered: false,
-        })
+            exit_reached: false,
+        }
+    }
+
+    pub fn stage(&self) -> &AgeStage {
+        self.life_cycle.stage(self.age)
     }
 
-    pub fn get_sight_multiplier(&self, game_handle: &RaylibHandle) -> f32 {
+    pub fn get_sight_multiplier(&self, time: f64) -> f32 {
         if self.is_dying {
-            let elapsed = game_handle.get_time() - self.death_start_time;
+            let elapsed = time - self.death_start_time;
             let progress = (elapsed / DEATH_ANIMATION_DURATION) as f32;
             self.current_sight * (1.0_f32 - progress.min(1.0))
         } else {
@@ -213,92 +156,123 @@ impl Player {
         }
     }
 
-    pub fn draw<D: RaylibDraw>(&mut self, d: &mut D) {
+    // Part of the sprite sheet for the current animation frame, flipped to the facing
+    pub fn sprite_source(&self) -> Rect {
         let (sprite_position, sprite_y) = match &self.state {
             PlayerState::Death { count, .. } => (*count as f32 * SPRITE_SIZE, 5.0 * SPRITE_SIZE),
-            PlayerState::Idle => (0.0, self.age.to_value() * SPRITE_SIZE),
+            PlayerState::Idle => (0.0, self.stage().sprite_row as f32 * SPRITE_SIZE),
             PlayerState::Walk { count, .. } | PlayerState::Jump { count, .. } => (
                 *count as f32 * SPRITE_SIZE,
-                self.age.to_value() * SPRITE_SIZE,
+                self.stage().sprite_row as f32 * SPRITE_SIZE,
             ),
         };
 
-        //d.draw_rectangle_rec(self.collision_box, Color::PURPLE);
-
-        d.draw_texture_pro(
-            &self.sprite,
-            Rectangle {
-                x: sprite_position,
-                y: sprite_y,
-                width: SPRITE_SIZE * self.facing.to_value(),
-                height: SPRITE_SIZE,
-            },
-            Rectangle {
-                x: self.body.x,
-                y: self.body.y,
-                width: PLAYER_SCALE * SPRITE_SIZE,
-                height: PLAYER_SCALE * SPRITE_SIZE,
-            },
-            Vector2::zero(),
-            0.0,
-            Color::WHITE,
-        );
+        Rect {
+            x: sprite_position,
+            y: sprite_y,
+            width: SPRITE_SIZE * self.facing.to_value(),
+            height: SPRITE_SIZE,
+        }
     }
 
-    fn increment_age(&mut self, game_handle: &mut RaylibHandle) {
+    fn increment_age(&mut self, clock: &Clock, map: &WorldMap) {
         if !self.can_age {
             return;
         }
 
-        let time_to_change = match &self.age {
-            Age::Baby => BABY_TIME_TO_CHANGE,
-            Age::Child => CHILD_TIME_TO_CHANGE,
-            Age::Teenager => TEENAGER_TIME_TO_CHANGE,
-            Age::Adult => ADULT_TIME_TO_CHANGE,
-            Age::Elder => ELDER_TIME_TO_CHANGE,
-        };
+        self.age_timer += clock.delta as f64;
 
-        let time_in_life = game_handle.get_time() as u32 % LIFETIME as u32;
-
-        if time_to_change as u32 == time_in_life {
-            self.age = match &self.age {
-                Age::Baby => Age::Child,
-                Age::Child => Age::Teenager,
-                Age::Teenager => Age::Adult,
-                Age::Adult => Age::Elder,
-                Age::Elder => {
-                    self.is_dying = true;
-                    self.death_start_time = game_handle.get_time();
-                    self.vel = (0.0, 0.0);
-                    self.state = PlayerState::Death {
-                        count: 0,
-                        last_update: game_handle.get_time(),
-                    };
-                    Age::Elder
-                }
-            };
-            if !self.is_dying {
-                self.target_sight = self.age.attributes().sight;
+        let time_to_change = self.life_cycle.time_to_change(self.age);
+
+        if self.age_timer < time_to_change {
+            return;
+        }
+
+        match self.life_cycle.next(self.age) {
+            Some(age) => {
+                self.age = age;
+                self.target_sight = self.stage().sight;
+                self.resize_collision_box(map);
+                self.age_events.push(AgeEvent::Aged(self.age));
+            }
+            None => {
+                self.is_dying = true;
+                self.death_start_time = clock.time;
+                self.vel = (0.0, 0.0);
+                self.state = PlayerState::Death {
+                    count: 0,
+                    last_update: clock.time,
+                };
+                self.age_events.push(AgeEvent::Died);
             }
         }
     }
 
+    // Keeps the feet in place, the old height is kept if the new one would clip
+    fn resize_collision_box(&mut self, map: &WorldMap) {
+        let previous = self.collision_box;
+        let height = PLAYER_SCALE * self.stage().collision_box_height;
+        self.collision_box.y += self.collision_box.height - height;
+        self.collision_box.height = height;
+
+        if self.collides(map, None).is_some() {
+            self.collision_box = previous;
+        }
+    }
+
     pub fn respawn(&mut self) {
-        self.age = Age::Baby;
+        self.lives += 1;
+        self.age_events.push(AgeEvent::Reborn);
+        self.age = Age::default();
+        self.age_timer = 0.0;
         self.body.x = self.spawn_position.0;
         self.body.y = self.spawn_position.1;
+        self.previous_position = self.spawn_position;
         self.collision_box.x = self.spawn_position.0 + SPRITE_SIZE / 4.0;
         self.collision_box.y = self.spawn_position.1 + SPRITE_SIZE / 4.0;
-        self.collision_box.height = PLAYER_SCALE * Age::Baby.collision_box_height();
+        self.collision_box.height = PLAYER_SCALE * self.stage().collision_box_height;
         self.vel = (0.0, 0.0);
         self.state = PlayerState::Idle;
         self.grounded = true;
         self.is_dying = false;
-        self.current_sight = Age::Baby.attributes().sight;
-        self.target_sight = Age::Baby.attributes().sight;
+        self.current_sight = self.stage().sight;
+        self.target_sight = self.stage().sight;
+        self.can_age = true;
+        self.end_scene_active = false;
+        self.end_triggered = false;
+        self.exit_reached = false;
+    }
+
+    // Moves the body and collision box together, for things that push the player around
+    pub fn shift(&mut self, dx: f32, dy: f32) {
+        self.body.x += dx;
+        self.body.y += dy;
+        self.collision_box.x += dx;
+        self.collision_box.y += dy;
+    }
+
+    pub fn land_on(&mut self, top: f32) {
+        let bottom = self.collision_box.y + self.collision_box.height;
+        self.shift(0.0, top - bottom);
+        self.vel.1 = 0.0;
+        self.grounded = true;
+    }
+
+    pub fn teleport(&mut self, x: f32, y: f32) {
+        let (dx, dy) = (x - self.body.x, y - self.body.y);
+        self.body.x = x;
+        self.body.y = y;
+        self.collision_box.x += dx;
+        self.collision_box.y += dy;
+        self.previous_position = (x, y);
+        self.spawn_position = (x, y);
+        self.vel = (0.0, 0.0);
+        // A stop or end scene from the last level must not follow into this one
         self.can_age = true;
         self.end_scene_active = false;
+        self.target_sight = self.stage().sight;
         self.end_triggered = false;
+        self.exit_reached = false;
     }
 
     pub fn stop_aging(&mut self) {
@@ -309,7 +283,13 @@ impl Player {
         }
     }
 
-    fn trigger_end_scene(&mut self) {
+    pub fn resume_aging(&mut self) {
+        if !self.can_age {
+            self.can_age = true;
+        }
+    }
+
+    pub fn trigger_end_scene(&mut self) {
         if !self.end_triggered {
             self.end_triggered = true;
             self.target_sight = END_SCENE_SIGHT_MULTIPLIER;
@@ -317,30 +297,12 @@ impl Player {
         }
     }
 
-    fn check_end_proximity(&mut self, map: &WorldMap) {
-        if self.end_triggered {
-            return;
-        }
-
-        for ((x, y), b) in map {
-            if *b == BlockType::End {
-                let nx = (*x as f32) * BLOCK_SIZE as f32;
-                let ny = (*y as f32) * BLOCK_SIZE as f32;
-                let dx = (self.collision_box.x - nx).abs() / BLOCK_SIZE as f32;
-                let dy = (self.collision_box.y - ny).abs() / BLOCK_SIZE as f32;
-
-                if dx <= 4.0 && dy <= 4.0 {
-                    self.trigger_end_scene();
-                    return;
-                }
-            }
-        }
-    }
+    pub fn after_move(&mut self, input: &Input, clock: &Clock, map: &WorldMap) -> bool {
+        self.previous_position = (self.body.x, self.body.y);
 
-    pub fn after_move(&mut self, game_handle: &mut RaylibHandle, map: &mut WorldMap) -> bool {
         if self.is_dying {
-            self.state.increment_count(game_handle);
-            let elapsed = game_handle.get_time() - self.death_start_time;
+            self.state.increment_count(clock.time);
+            let elapsed = clock.time - self.death_start_time;
             if elapsed >= DEATH_ANIMATION_DURATION {
                 self.respawn();
             }
@@ -350,16 +312,16 @@ impl Player {
         let mut frame_advanced = false;
         let mut moved = false;
 
-        self.increment_age(game_handle);
+        self.increment_age(clock, map);
 
-        let attrs = self.age.attributes();
+        let attrs = self.stage().attributes();
 
         let speed_multiplier = attrs.speed;
-        if game_handle.is_key_down(KeyboardKey::KEY_RIGHT) {
+        if input.right {
             self.vel.0 = PLAYER_SPEED * speed_multiplier;
             self.facing = Facing::Right;
             moved = true;
-        } else if game_handle.is_key_down(KeyboardKey::KEY_LEFT) {
+        } else if input.left {
             self.vel.0 = -PLAYER_SPEED * speed_multiplier;
             self.facing = Facing::Left;
             moved = true;
@@ -368,21 +330,17 @@ impl Player {
         }
 
         let jump_multiplier = attrs.strength;
-        let current_time = game_handle.get_time();
+        let current_time = clock.time;
         let time_since_jump = current_time - self.last_jump_time;
 
-        if (game_handle.is_key_down(KeyboardKey::KEY_UP)
-            || game_handle.is_key_down(KeyboardKey::KEY_SPACE))
-            && self.grounded
-            && time_since_jump >= attrs.jump_cooldown as f64
-        {
+        if input.jump && self.grounded && time_since_jump >= attrs.jump_cooldown as f64 {
             self.vel.1 = -JUMP_SPEED * jump_multiplier;
             self.grounded = false;
             moved = true;
             self.last_jump_time = current_time;
             self.state = PlayerState::Jump {
                 count: PLAYER_SPRITE_WALK_INIT,
-                last_update: game_handle.get_time(),
+                last_update: current_time,
             };
         }
 
@@ -390,7 +348,7 @@ impl Player {
 
         self.body.x += self.vel.0;
         self.collision_box.x += self.vel.0;
-        if let Some(block) = self.collides(map) {
+        if let Some(block) = self.collides(map, None) {
             if self.vel.0 > 0.0 {
                 self.body.x =
                     block.x - self.collision_box.width - (PLAYER_COLLISION_BOX_WIDTH / 2.0);
@@ -402,9 +360,12 @@ impl Player {
             self.vel.0 = 0.0;
         }
 
+        // Holding down lets the player fall through one-way platforms
+        let previous_bottom = (self.vel.1 > 0.0 && !input.down)
+            .then_some(self.collision_box.y + self.collision_box.height);
         self.body.y += self.vel.1;
         self.collision_box.y += self.vel.1;
-        if let Some(block) = self.collides(map) {
+        if let Some(block) = self.collides(map, previous_bottom) {
             if self.vel.1 > 0.0 {
                 self.body.y = block.y - self.body.height;
                 self.collision_box.y = block.y - self.collision_box.height;
@@ -419,20 +380,19 @@ impl Player {
         }
 
         // Handle special zones
-        self.check_stop_aging(map);
-        self.check_end_proximity(map);
+        self.exit_reached = self.touches(map, BlockType::Exit);
 
         if moved {
             match self.state {
                 PlayerState::Idle => {
                     self.state = PlayerState::Walk {
                         count: PLAYER_SPRITE_WALK_INIT,
-                        last_update: game_handle.get_time(),
+                        last_update: clock.time,
                     };
                     frame_advanced = true;
                 }
                 PlayerState::Walk { .. } | PlayerState::Jump { .. } => {
-                    frame_advanced = self.state.increment_count(game_handle);
+                    frame_advanced = self.state.increment_count(clock.time);
                 }
                 _ => {}
             }
@@ -443,51 +403,18 @@ impl Player {
         frame_advanced && self.grounded && moved
     }
 
-    fn check_stop_aging(&mut self, map: &WorldMap) {
-        for ((x, y), b) in map {
-            if *b == BlockType::StopAging {
-                let nx = (*x as f32) * BLOCK_SIZE as f32;
-                let ny = (*y as f32) * BLOCK_SIZE as f32;
-
-                let block_rect = Rectangle {
-                    x: nx,
-                    y: ny,
-                    width: BLOCK_SIZE as f32,
-                    height: BLOCK_SIZE as f32,
-                };
-
-                if block_rect.check_collision_recs(&self.collision_box) {
-                    self.stop_aging();
-                    return;
-                }
-            }
-        }
+    pub fn touches(&self, map: &WorldMap, block_type: BlockType) -> bool {
+        map.blocks_in(&self.collision_box, 0)
+            .filter(|(_, b)| *b == block_type)
+            .any(|((x, y), _)| Rect::tile(x, y).overlaps(&self.collision_box))
     }
 
-    pub fn collides(&self, map: &WorldMap) -> Option<Rectangle> {
-        for ((x, y), b) in map {
-            if *b != BlockType::Blank && *b != BlockType::Start && *b != BlockType::StopAging {
-                let nx = (*x as f32) * BLOCK_SIZE as f32;
-                let ny = (*y as f32) * BLOCK_SIZE as f32;
-
-                let block_rect = match b {
-                    BlockType::End => Rectangle {
-                        x: nx,
-                        y: ny - DEVIL_HEIGHT + SPRITE_SIZE,
-                        width: BLOCK_SIZE as f32,
-                        height: DEVIL_HEIGHT,
-                    },
-                    _ => Rectangle {
-                        x: nx,
-                        y: ny,
-                        width: BLOCK_SIZE as f32,
-                        height: BLOCK_SIZE as f32,
-                    },
-                };
+    pub fn collides(&self, map: &WorldMap, previous_bottom: Option<f32>) -> Option<Rect> {
+        for ((x, y), b) in map.blocks_in(&self.collision_box, 0) {
+            let block_rect = Rect::tile(x, y);
 
-                if block_rect.check_collision_recs(&self.collision_box) {
-                    return Some(block_rect);
-                }
+            if b.stops(block_rect.y, previous_bottom) && block_rect.overlaps(&self.collision_box) {
+                return Some(block_rect);
             }
         }
         None
@@ -0,0 +1,42 @@
+use crate::*;
+
+// World space rectangle for the simulation, drawing converts it to a raylib one
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Rect {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Rect {
+    pub const fn new(x: f32, y: f32, width: f32, height: f32) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    // The tile at a grid position
+    pub fn tile(x: usize, y: usize) -> Self {
+        let size = BLOCK_SIZE as f32;
+        Self::new(x as f32 * size, y as f32 * size, size, size)
+    }
+
+    // Rectangles that only share an edge do not overlap, same as raylib's CheckCollisionRecs
+    pub fn overlaps(&self, other: &Rect) -> bool {
+        self.x < other.x + other.width
+            && self.x + self.width > other.x
+            && self.y < other.y + other.height
+            && self.y + self.height > other.y
+    }
+}
+
+#[cfg(feature = "render")]
+impl From<Rect> for Rectangle {
+    fn from(rect: Rect) -> Self {
+        Rectangle::new(rect.x, rect.y, rect.width, rect.height)
+    }
+}
@@ -0,0 +1,220 @@
+use crate::age::LifeCycle;
+use crate::campaign::Campaign;
+use crate::map_file::MapFile;
+use crate::simulation::{LevelState, Simulation};
+use crate::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::{error::Error, fs};
+
+// Changes made to the run between steps, by the pause menu or a dialogue's outcomes
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum ReplayEvent {
+    RestartLife,
+    NextLevel,
+    SetFlag(String),
+    ClearFlag(String),
+}
+
+impl ReplayEvent {
+    pub fn apply(&self, simulation: &mut Simulation) {
+        match self {
+            Self::RestartLife => simulation.player.respawn(),
+            Self::NextLevel => simulation.advance_level(),
+            Self::SetFlag(flag) => {
+                simulation.flags.insert(flag.clone());
+            }
+            Self::ClearFlag(flag) => {
+                simulation.flags.remove(flag);
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct RecordedEvent {
+    step: usize, // Applied before this step's input
+    time: f64,   // The clock runs on through the ending without steps
+    event: ReplayEvent,
+}
+
+// A run stored with its maps, scripts and life cycle so it plays back the same after files change
+#[derive(Serialize, Deserialize)]
+pub struct Replay {
+    pub seed: u32,
+    pub life_cycle: LifeCycle,
+    pub campaign: Campaign,
+    #[serde(default)]
+    pub level: usize, // Level the run started on, later than the first when continuing a save
+    #[serde(default)]
+    pub level_state: LevelState, // What earlier lives had already done to that level
+    #[serde(default)]
+    pub lives: u32,
+    #[serde(default)]
+    pub flags: HashSet<String>,
+    maps: Vec<MapFile>,
+    #[serde(default)]
+    scripts: Vec<Option<String>>,
+    inputs: Vec<u8>, // One per fixed step
+    #[serde(default)]
+    events: Vec<RecordedEvent>,
+    #[serde(skip)]
+    cursor: usize,
+    #[serde(skip)]
+    event_cursor: usize,
+}
+
+impl Replay {
+    pub fn new(seed: u32, simulation: &Simulation) -> Self {
+        Self {
+            seed,
+            life_cycle: simulation.player.life_cycle.clone(),
+            campaign: simulation.campaign.clone(),
+            level: simulation.level,
+            level_state: simulation.level_state(),
+            lives: simulation.player.lives,
+            flags: simulation.flags.clone(),
+            maps: simulation
+                .levels
+                .iter()
+                .map(|(header, map)| MapFile::new(header, map))
+                .collect(),
+            scripts: simulation.scripts.clone(),
+            inputs: Vec::new(),
+            events: Vec::new(),
+            cursor: 0,
+            event_cursor: 0,
+        }
+    }
+
+    pub fn simulation(&self) -> Result<Simulation, MapError> {
+        let levels = self
+            .maps
+            .iter()
+            .cloned()
+            .map(MapFile::into_map)
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut simulation =
+            Simulation::new(self.campaign.clone(), levels, self.life_cycle.clone());
+        simulation.set_scripts(self.scripts.clone());
+        if self.level > 0 {
+            simulation.load_level(self.level);
+        }
+        simulation.player.lives = self.lives;
+        simulation.flags = self.flags.clone();
+        simulation.restore_level_state(&self.level_state);
+        Ok(simulation)
+    }
+
+    pub fn record(&mut self, input: &Input) {
+        self.inputs.push(input.to_bits());
+    }
+
+    pub fn record_event(&mut self, time: f64, event: &ReplayEvent) {
+        self.events.push(RecordedEvent {
+            step: self.inputs.len(),
+            time,
+            event: event.clone(),
+        });
+    }
+
+    // Events due before the next input, with the clock time they were made at
+    pub fn take_events(&mut self) -> Vec<(f64, ReplayEvent)> {
+        let mut events = Vec::new();
+        while let Some(recorded) = self.events.get(self.event_cursor)
+            && recorded.step <= self.cursor
+        {
+            events.push((recorded.time, recorded.event.clone()));
+            self.event_cursor += 1;
+        }
+        events
+    }
+
+    pub fn next_input(&mut self) -> Option<Input> {
+        let bits = *self.inputs.get(self.cursor)?;
+        self.cursor += 1;
+        Some(Input::from_bits(bits))
+    }
+
+    pub fn steps(&self) -> usize {
+        self.inputs.len()
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        fs::write(path, serde_json::to_string(self)?)?;
+        println!("Replay saved to {} with {} steps", path, self.steps());
+        Ok(())
+    }
+}
+
+pub fn load_replay(path: &str) -> Result<Replay, Box<dyn Error>> {
+    let replay: Replay = serde_json::from_str(&fs::read_to_string(path)?)?;
+    println!("Loaded {} with {} steps", path, replay.steps());
+    Ok(replay)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::tests::{room, stage, two_levels};
+
+    fn simulation() -> Simulation {
+        two_levels(room(), room(), vec![stage("Young", 1.0), stage("Old", 1.0)])
+    }
+
+    // Walks back and forth and jumps, whatever the run looks like it has to come out the same
+    fn input(step: usize) -> Input {
+        Input {
+            left: step % 60 >= 30,
+            right: step % 60 < 30,
+            jump: step.is_multiple_of(45),
+            down: false,
+        }
+    }
+
+    #[test]
+    fn playback_ends_where_the_recorded_run_did() {
+        let mut recorded = simulation();
+        let mut replay = Replay::new(7, &recorded);
+        let mut clock = Clock::default();
+
+        for step in 0..400 {
+            // The first life ages out and respawns on its own before the level changes
+            if step == 330 {
+                replay.record_event(clock.time, &ReplayEvent::NextLevel);
+                ReplayEvent::NextLevel.apply(&mut recorded);
+            }
+            let input = input(step);
+            replay.record(&input);
+            clock.tick(FIXED_TIMESTEP as f32);
+            recorded.update(&input, &clock);
+        }
+        assert_eq!(recorded.level, 1);
+        assert_eq!(recorded.player.lives, 2);
+
+        let json = serde_json::to_string(&replay).unwrap();
+        let mut replay: Replay = serde_json::from_str(&json).unwrap();
+        let mut played = replay.simulation().unwrap();
+        let mut clock = Clock::default();
+        loop {
+            for (time, event) in replay.take_events() {
+                clock.time = time;
+                event.apply(&mut played);
+            }
+            let Some(input) = replay.next_input() else {
+                break;
+            };
+            clock.tick(FIXED_TIMESTEP as f32);
+            played.update(&input, &clock);
+        }
+
+        let position = |simulation: &Simulation| {
+            let body = simulation.player.body;
+            (body.x, body.y)
+        };
+        assert_eq!(position(&played), position(&recorded));
+        assert_eq!(played.player.age, recorded.player.age);
+        assert_eq!(played.player.lives, recorded.player.lives);
+        assert_eq!(played.level, recorded.level);
+    }
+}
@@ -0,0 +1,133 @@
+use crate::simulation::LevelState;
+use crate::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::{error::Error, fs};
+
+// Progress kept between sessions, written on every respawn
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SaveData {
+    pub lives: u32,
+    pub level: usize,
+    pub unlocked_levels: usize, // Levels reached so far, counting the first
+    pub end_reached: bool,
+    pub has_laughed: bool,
+    #[serde(default)]
+    pub flags: HashSet<String>,
+    #[serde(default)]
+    pub level_state: LevelState,
+}
+
+impl SaveData {
+    pub fn new(simulation: &Simulation, end_reached: bool, has_laughed: bool) -> Self {
+        Self {
+            lives: simulation.player.lives,
+            level: simulation.level,
+            unlocked_levels: simulation.unlocked_levels,
+            end_reached,
+            has_laughed,
+            flags: simulation.flags.clone(),
+            level_state: simulation.level_state(),
+        }
+    }
+
+    // The same progress picked up on another level already reached, which starts untouched
+    pub fn at_level(mut self, level: usize) -> Self {
+        if level != self.level {
+            self.level = level;
+            self.level_state = LevelState::default();
+        }
+        self
+    }
+
+    // Puts a fresh run back where the save left off, levels removed since are skipped
+    pub fn restore(&self, simulation: &mut Simulation) {
+        let last = simulation.levels.len() - 1;
+        simulation.load_level(self.level.min(last));
+        self.keep_unlocked(simulation);
+        simulation.player.lives = self.lives;
+        simulation.flags = self.flags.clone();
+
+        if simulation.level == self.level {
+            simulation.restore_level_state(&self.level_state);
+        }
+    }
+
+    // A new game starts over but the levels already reached stay open,
+    // so its first autosave does not take them away again
+    pub fn keep_unlocked(&self, simulation: &mut Simulation) {
+        let unlocked = self.unlocked_levels.clamp(1, simulation.levels.len());
+        simulation.unlocked_levels = simulation.unlocked_levels.max(unlocked);
+    }
+}
+
+pub fn load_save() -> Option<SaveData> {
+    match fs::read_to_string(SAVE_PATH) {
+        Ok(content) => match serde_json::from_str::<SaveData>(&content) {
+            Ok(save) => {
+                println!("Loaded {}", SAVE_PATH);
+                Some(save)
+            }
+            Err(e) => {
+                eprintln!("Failed to parse {}: {}", SAVE_PATH, e);
+                None
+            }
+        },
+        Err(_) => {
+            println!("No {} found", SAVE_PATH);
+            None
+        }
+    }
+}
+
+pub fn save_game(save: &SaveData) -> Result<(), Box<dyn Error>> {
+    fs::write(SAVE_PATH, serde_json::to_string_pretty(save)?)?;
+    println!("Progress saved to {}", SAVE_PATH);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::age::Age;
+    use crate::simulation::tests::{room, stage, two_levels};
+
+    fn simulation() -> Simulation {
+        two_levels(room(), room(), vec![stage("Young", 1.0), stage("Old", 1.0)])
+    }
+
+    #[test]
+    fn a_run_comes_back_from_its_save() {
+        let mut played = simulation();
+        played.advance_level();
+        played.player.lives = 4;
+        played.player.age = Age(1);
+        played.flags.insert("pact".to_string());
+        played.change((5, 9), BlockType::Grave);
+
+        let json = serde_json::to_string(&SaveData::new(&played, true, false)).unwrap();
+        let save: SaveData = serde_json::from_str(&json).unwrap();
+        let mut restored = simulation();
+        save.restore(&mut restored);
+
+        assert_eq!(restored.level, 1);
+        assert_eq!(restored.unlocked_levels, 2);
+        assert_eq!(restored.player.lives, 4);
+        assert_eq!(restored.player.age, Age(0)); // Saves are made on respawn, so they hold no age
+        assert_eq!(restored.flags, played.flags);
+        assert_eq!(restored.map.get((5, 9)), Some(BlockType::Grave));
+        assert!(save.end_reached && !save.has_laughed);
+    }
+
+    #[test]
+    fn a_new_game_keeps_the_levels_already_reached() {
+        let mut played = simulation();
+        played.advance_level();
+        let save = SaveData::new(&played, false, false);
+
+        let mut new_game = simulation();
+        save.keep_unlocked(&mut new_game);
+        assert_eq!(new_game.level, 0);
+        assert_eq!(SaveData::new(&new_game, false, false).unlocked_levels, 2);
+    }
+}
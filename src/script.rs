@@ -0,0 +1,268 @@
+use crate::*;
+use rhai::{AST, Dynamic, Engine, Map, Scope};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::mem;
+use std::rc::Rc;
+
+// What a script asked for, applied by the simulation once the call returns
+#[derive(Clone, Debug)]
+pub enum ScriptCommand {
+    Action(TriggerAction),
+    SetTile((usize, usize), BlockType),
+    ClearFlag(String),
+}
+
+// The run as the script sees it, copied in before every call
+#[derive(Default)]
+struct ScriptState {
+    player: (f32, f32), // Top left of the collision box, in tiles
+    age: usize,
+    age_name: String,
+    age_time: f64,
+    lives: u32,
+    grounded: bool,
+    dying: bool,
+    can_age: bool,
+    level: usize,
+    flags: HashSet<String>, // Lent by the simulation for the length of a call, like the map
+    map: WorldMap,
+    vars: Map, // Kept between calls and in saves, script functions cannot see the script's globals
+    commands: Vec<ScriptCommand>,
+}
+
+impl ScriptState {
+    fn sync(&mut self, simulation: &Simulation) {
+        let player = &simulation.player;
+        self.player = (
+            player.collision_box.x / BLOCK_SIZE as f32,
+            player.collision_box.y / BLOCK_SIZE as f32,
+        );
+        self.age = player.age.0;
+        self.age_name = player.stage().name.clone();
+        self.age_time = player.age_timer;
+        self.lives = player.lives;
+        self.grounded = player.grounded;
+        self.dying = player.is_dying;
+        self.can_age = player.can_age;
+        self.level = simulation.level;
+    }
+
+    fn push(&mut self, command: ScriptCommand) {
+        self.commands.push(command);
+    }
+}
+
+// Block types are named in scripts the way they are in map files
+fn block_type(name: &str) -> Option<BlockType> {
+    serde_json::from_value(serde_json::Value::String(name.to_string())).ok()
+}
+
+// A level's script sits next to its map, map.json goes with map.rhai
+pub fn script_path(map: &str) -> String {
+    match map.rsplit_once('.') {
+        Some((stem, _)) => format!("{}.rhai", stem),
+        None => format!("{}.rhai", map),
+    }
+}
+
+// Levels without a script file simply have none
+pub fn load_source(path: &str) -> Option<String> {
+    let source = fs::read_to_string(path).ok()?;
+    println!("Loaded {}", path);
+    Some(source)
+}
+
+pub struct LevelScript {
+    engine: Engine,
+    ast: AST,
+    scope: Scope<'static>,
+    state: Rc<RefCell<ScriptState>>,
+    pub started: bool, // on_start has run for this visit to the level
+    broken: bool,
+}
+
+impl LevelScript {
+    pub fn compile(source: &str) -> Result<Self, String> {
+        let state = Rc::new(RefCell::new(ScriptState::default()));
+        let mut engine = Engine::new();
+        // A script stuck in a loop would freeze the game
+        engine.set_max_operations(SCRIPT_MAX_OPERATIONS);
+        Self::register(&mut engine, &state);
+
+        let ast = engine.compile(source).map_err(|e| e.to_string())?;
+        let mut scope = Scope::new();
+        engine
+            .run_ast_with_scope(&mut scope, &ast)
+            .map_err(|e| e.to_string())?;
+
+        Ok(Self {
+            engine,
+            ast,
+            scope,
+            state,
+            started: false,
+            broken: false,
+        })
+    }
+
+    fn register(engine: &mut Engine, state: &Rc<RefCell<ScriptState>>) {
+        let s = state.clone();
+        engine.register_fn("player_x", move || s.borrow().player.0 as f64);
+        let s = state.clone();
+        engine.register_fn("player_y", move || s.borrow().player.1 as f64);
+        let s = state.clone();
+        engine.register_fn("age", move || s.borrow().age as i64);
+        let s = state.clone();
+        engine.register_fn("age_name", move || s.borrow().age_name.clone());
+        let s = state.clone();
+        engine.register_fn("age_time", move || s.borrow().age_time);
+        let s = state.clone();
+        engine.register_fn("lives", move || s.borrow().lives as i64);
+        let s = state.clone();
+        engine.register_fn("is_grounded", move || s.borrow().grounded);
+        let s = state.clone();
+        engine.register_fn("is_dying", move || s.borrow().dying);
+        let s = state.clone();
+        engine.register_fn("can_age", move || s.borrow().can_age);
+        let s = state.clone();
+        engine.register_fn("level", move || s.borrow().level as i64);
+        let s = state.clone();
+        engine.register_fn("has_flag", move |flag: &str| {
+            s.borrow().flags.contains(flag)
+        });
+
+        // Empty for tiles that hold nothing or lie outside the map
+        let s = state.clone();
+        engine.register_fn("tile", move |x: i64, y: i64| {
+            let state = s.borrow();
+            let block = (x >= 0 && y >= 0)
+                .then(|| state.map.get((x as usize, y as usize)))
+                .flatten();
+            block.map_or(String::new(), |b| format!("{:?}", b))
+        });
+
+        let s = state.clone();
+        engine.register_fn("get_var", move |name: &str| {
+            s.borrow().vars.get(name).cloned().unwrap_or(Dynamic::UNIT)
+        });
+        let s = state.clone();
+        engine.register_fn("set_var", move |name: &str, value: Dynamic| {
+            s.borrow_mut().vars.insert(name.into(), value);
+        });
+
+        let s = state.clone();
+        engine.register_fn(
+            "set_tile",
+            move |x: i64, y: i64, name: &str| match block_type(name) {
+                Some(block) if x >= 0 && y >= 0 => s
+                    .borrow_mut()
+                    .push(ScriptCommand::SetTile((x as usize, y as usize), block)),
+                _ => eprintln!("Script cannot place \"{}\" at ({}, {})", name, x, y),
+            },
+        );
+        let s = state.clone();
+        engine.register_fn("set_flag", move |flag: &str| {
+            s.borrow_mut()
+                .push(ScriptCommand::Action(TriggerAction::SetFlag(flag.into())));
+        });
+        let s = state.clone();
+        engine.register_fn("clear_flag", move |flag: &str| {
+            s.borrow_mut().push(ScriptCommand::ClearFlag(flag.into()));
+        });
+        let s = state.clone();
+        engine.register_fn("stop_aging", move || {
+            s.borrow_mut()
+                .push(ScriptCommand::Action(TriggerAction::StopAging));
+        });
+        let s = state.clone();
+        engine.register_fn("resume_aging", move || {
+            s.borrow_mut()
+                .push(ScriptCommand::Action(TriggerAction::ResumeAging));
+        });
+        let s = state.clone();
+        engine.register_fn("end_scene", move || {
+            s.borrow_mut()
+                .push(ScriptCommand::Action(TriggerAction::EndScene));
+        });
+        let s = state.clone();
+        engine.register_fn("move_camera", move |x: f64, y: f64| {
+            s.borrow_mut()
+                .push(ScriptCommand::Action(TriggerAction::MoveCamera(
+                    x as f32, y as f32,
+                )));
+        });
+        let s = state.clone();
+        engine.register_fn("move_camera", move |x: i64, y: i64| {
+            s.borrow_mut()
+                .push(ScriptCommand::Action(TriggerAction::MoveCamera(
+                    x as f32, y as f32,
+                )));
+        });
+        let s = state.clone();
+        engine.register_fn("start_dialogue", move |node: &str| {
+            s.borrow_mut()
+                .push(ScriptCommand::Action(TriggerAction::StartDialogue(
+                    node.into(),
+                )));
+        });
+        let s = state.clone();
+        engine.register_fn("play_music", move |path: &str| {
+            s.borrow_mut()
+                .push(ScriptCommand::Action(TriggerAction::ChangeMusic(
+                    path.into(),
+                )));
+        });
+        let s = state.clone();
+        engine.register_fn("play_sound", move |name: &str| {
+            s.borrow_mut()
+                .push(ScriptCommand::Action(TriggerAction::PlaySound(name.into())));
+        });
+    }
+
+    pub fn vars(&self) -> BTreeMap<String, Dynamic> {
+        let state = self.state.borrow();
+        state
+            .vars
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.clone()))
+            .collect()
+    }
+
+    // Values kept by an earlier session on this level, from a save or a replay
+    pub fn set_vars(&mut self, vars: &BTreeMap<String, Dynamic>) {
+        self.state.borrow_mut().vars = vars
+            .iter()
+            .map(|(name, value)| (name.into(), value.clone()))
+            .collect();
+    }
+
+    // Missing functions are skipped, a failing script is switched off instead of failing every step
+    // The map and flags are moved in rather than copied, on_update runs every step
+    pub fn call(&mut self, function: &str, simulation: &mut Simulation) -> Vec<ScriptCommand> {
+        let defined = self.ast.iter_functions().any(|f| f.name == function);
+        if self.broken || !defined {
+            return Vec::new();
+        }
+
+        {
+            let mut state = self.state.borrow_mut();
+            state.sync(simulation);
+            state.map = mem::replace(&mut simulation.map, WorldMap::new(0, 0));
+            state.flags = mem::take(&mut simulation.flags);
+        }
+        let result = self
+            .engine
+            .call_fn::<Dynamic>(&mut self.scope, &self.ast, function, ());
+        if let Err(e) = result {
+            eprintln!("Script error in {}(): {}, script disabled", function, e);
+            self.broken = true;
+        }
+
+        let mut state = self.state.borrow_mut();
+        simulation.map = mem::replace(&mut state.map, WorldMap::new(0, 0));
+        simulation.flags = mem::take(&mut state.flags);
+        mem::take(&mut state.commands)
+    }
+}
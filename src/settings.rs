@@ -0,0 +1,44 @@
+use crate::*;
+use serde::{Deserialize, Serialize};
+use std::{error::Error, fs};
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum DialogueAdvance {
+    #[default]
+    Auto, // Lines move on by themselves after their wait
+    Manual, // Lines wait for Confirm
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Settings {
+    #[serde(flatten)]
+    pub controls: Controls,
+    #[serde(default)]
+    pub dialogue_advance: DialogueAdvance,
+}
+
+pub fn load_settings() -> Settings {
+    match fs::read_to_string(SETTINGS_PATH) {
+        Ok(content) => match serde_json::from_str::<Settings>(&content) {
+            Ok(mut settings) => {
+                settings.controls.fill_defaults();
+                println!("Loaded {}", SETTINGS_PATH);
+                settings
+            }
+            Err(e) => {
+                eprintln!("Failed to parse {}: {}", SETTINGS_PATH, e);
+                Settings::default()
+            }
+        },
+        Err(_) => {
+            println!("No {} found, using default settings.", SETTINGS_PATH);
+            Settings::default()
+        }
+    }
+}
+
+pub fn save_settings(settings: &Settings) -> Result<(), Box<dyn Error>> {
+    fs::write(SETTINGS_PATH, serde_json::to_string_pretty(settings)?)?;
+    println!("Settings saved to {}", SETTINGS_PATH);
+    Ok(())
+}
@@ -0,0 +1,687 @@
+use crate::age::LifeCycle;
+use crate::campaign::{Campaign, Level, LevelEnding};
+use crate::player::AgeEvent;
+use crate::script::{LevelScript, ScriptCommand};
+use crate::*;
+use rhai::Dynamic;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::mem;
+
+// What earlier lives did to the current level, kept in saves and replays
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct LevelState {
+    #[serde(default)]
+    pub changes: Vec<((usize, usize), BlockType)>,
+    #[serde(default)]
+    pub entities: Vec<(f32, f32)>,
+    #[serde(default)]
+    pub fired_triggers: Vec<usize>,
+    #[serde(default)]
+    pub script_vars: BTreeMap<String, Dynamic>, // What the level's script kept with set_var
+}
+
+// Everything a run needs besides rendering, so it can also step without a window
+pub struct Simulation {
+    pub campaign: Campaign,
+    pub levels: Vec<(MapHeader, WorldMap)>,
+    pub level: usize,
+    pub map: WorldMap,
+    pub player: Player,
+    pub unlocked_levels: usize, // Levels reached so far, counting the first
+    pub flags: HashSet<String>, // Story flags set by dialogue outcomes and triggers
+    pub changes: HashMap<(usize, usize), BlockType>, // Marks left on this level, the latest per tile
+    pub entities: Vec<Entity>,
+    pub triggers: TriggerState,
+    pub trigger_events: Vec<TriggerAction>, // Left for the game to play, like dialogue and music
+    pub scripts: Vec<Option<String>>,       // Source of each level's script, if it has one
+    script: Option<LevelScript>,
+}
+
+impl Simulation {
+    pub fn new(
+        campaign: Campaign,
+        levels: Vec<(MapHeader, WorldMap)>,
+        life_cycle: LifeCycle,
+    ) -> Self {
+        let (header, map) = &levels[0];
+        let spawn_pos = Self::find_spawn_position(header, map);
+        let player = Player::new(spawn_pos.0, spawn_pos.1, life_cycle);
+        let map = map.clone();
+        let entities = Self::spawn_entities(&map);
+        let triggers = TriggerState::new(map.triggers().len());
+
+        Self {
+            campaign,
+            levels,
+            level: 0,
+            map,
+            player,
+            unlocked_levels: 1,
+            flags: HashSet::new(),
+            changes: HashMap::new(),
+            entities,
+            triggers,
+            trigger_events: Vec::new(),
+            scripts: Vec::new(),
+            script: None,
+        }
+    }
+
+    pub fn set_scripts(&mut self, scripts: Vec<Option<String>>) {
+        self.scripts = scripts;
+        self.load_script();
+    }
+
+    // A script that does not compile leaves the level playing without one
+    fn load_script(&mut self) {
+        let source = self.scripts.get(self.level).cloned().flatten();
+        self.script = source.and_then(|source| match LevelScript::compile(&source) {
+            Ok(script) => Some(script),
+            Err(e) => {
+                eprintln!(
+                    "Failed to compile the script of {}: {}",
+                    self.current_level().name,
+                    e
+                );
+                None
+            }
+        });
+    }
+
+    pub fn update(&mut self, input: &Input, clock: &Clock) -> bool {
+        for i in 0..self.entities.len() {
+            let others = self.bodies_except(i);
+            self.entities[i].update(&self.map, &others);
+        }
+
+        let was_dying = self.player.is_dying;
+        let footstep = self.player.after_move(input, clock, &self.map);
+        self.player.update_sight(clock.delta);
+        self.collide_entities();
+        self.fire_triggers();
+        self.leave_marks(was_dying);
+        self.run_script_hooks();
+
+        let reached_end =
+            self.player.end_triggered && self.current_level().ending == LevelEnding::NextLevel;
+        if self.player.exit_reached || reached_end {
+            self.advance_level();
+        }
+
+        footstep
+    }
+
+    pub fn current_level(&self) -> &Level {
+        &self.campaign.levels[self.level]
+    }
+
+    pub fn advance_level(&mut self) {
+        let next = self.level + 1;
+        if next >= self.levels.len() {
+            return;
+        }
+        self.load_level(next);
+    }
+
+    pub fn load_level(&mut self, index: usize) {
+        let (header, map) = &self.levels[index];
+        let spawn_pos = Self::find_spawn_position(header, map);
+        self.map = map.clone();
+        self.entities = Self::spawn_entities(&self.map);
+        self.triggers = TriggerState::new(self.map.triggers().len());
+        self.changes.clear();
+        self.level = index;
+        self.unlocked_levels = self.unlocked_levels.max(index + 1);
+        self.load_script();
+
+        self.player.teleport(spawn_pos.0, spawn_pos.1);
+    }
+
+    pub fn level_state(&self) -> LevelState {
+        LevelState {
+            changes: self.sorted_changes(),
+            entities: self.entities.iter().map(Entity::position).collect(),
+            fired_triggers: (0..self.triggers.fired.len())
+                .filter(|i| self.triggers.fired[*i])
+                .collect(),
+            script_vars: self
+                .script
+                .as_ref()
+                .map(LevelScript::vars)
+                .unwrap_or_default(),
+        }
+    }
+
+    // In tile order so saves and replays come out the same every time
+    fn sorted_changes(&self) -> Vec<((usize, usize), BlockType)> {
+        let mut changes: Vec<_> = self.changes.iter().map(|(pos, bt)| (*pos, *bt)).collect();
+        changes.sort_by_key(|((x, y), _)| (*y, *x));
+        changes
+    }
+
+    // Positions only come back if the map still starts with as many entities
+    pub fn restore_level_state(&mut self, state: &LevelState) {
+        for (pos, block_type) in &state.changes {
+            self.change(*pos, *block_type);
+        }
+        if state.entities.len() == self.entities.len() {
+            for (entity, (x, y)) in self.entities.iter_mut().zip(&state.entities) {
+                *entity = Entity::new(entity.kind, *x, *y);
+            }
+        }
+        for i in &state.fired_triggers {
+            if let Some(fired) = self.triggers.fired.get_mut(*i) {
+                *fired = true;
+            }
+        }
+        if let Some(script) = &mut self.script {
+            script.set_vars(&state.script_vars);
+        }
+    }
+
+    fn spawn_entities(map: &WorldMap) -> Vec<Entity> {
+        map.entities().iter().map(Entity::spawn).collect()
+    }
+
+    fn bodies_except(&self, index: usize) -> Vec<Rect> {
+        let others = self
+            .entities
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| *i != index);
+        others.map(|(_, entity)| entity.body).collect()
+    }
+
+    // Entities stop the player like walls, pushable ones give way to a strong enough player
+    fn collide_entities(&mut self) {
+        let strong = self.player.stage().attributes().strength >= BOULDER_MIN_STRENGTH;
+
+        for i in 0..self.entities.len() {
+            let others = self.bodies_except(i);
+            let entity = &mut self.entities[i];
+            let player = self.player.collision_box;
+            if !player.overlaps(&entity.body) {
+                continue;
+            }
+
+            let overlap_x = (player.x + player.width).min(entity.body.x + entity.body.width)
+                - player.x.max(entity.body.x);
+            let overlap_y = (player.y + player.height).min(entity.body.y + entity.body.height)
+                - player.y.max(entity.body.y);
+
+            // Mostly overlapping from above or below
+            if overlap_y < overlap_x {
+                if player.y < entity.body.y {
+                    self.player.land_on(entity.body.y);
+                } else if entity.kind.has_gravity() {
+                    // Falling onto the player's head it comes to rest there
+                    entity.body.y = player.y - entity.body.height;
+                    entity.vel.1 = 0.0;
+                    entity.grounded = true;
+                } else {
+                    self.player.shift(0.0, overlap_y);
+                    self.player.vel.1 = self.player.vel.1.max(0.0);
+                }
+                continue;
+            }
+
+            let direction = if player.x < entity.body.x { 1.0 } else { -1.0 };
+            let pushing = self.player.vel.0 * direction > 0.0;
+            let pushed = if entity.kind.is_pushable() && strong && pushing {
+                entity.move_x(direction * overlap_x, &self.map, &others)
+            } else {
+                0.0
+            };
+
+            // Whatever the entity did not give way is taken back from the player
+            self.player.shift(pushed - direction * overlap_x, 0.0);
+        }
+    }
+
+    fn fire_triggers(&mut self) {
+        for i in self.triggers.entered(self.map.triggers(), &self.player) {
+            let action = self.map.triggers()[i].action.clone();
+            self.apply(action);
+        }
+    }
+
+    // What the game has to present is queued in trigger_events instead
+    fn apply(&mut self, action: TriggerAction) {
+        match action {
+            TriggerAction::EndScene => self.player.trigger_end_scene(),
+            TriggerAction::StopAging => self.player.stop_aging(),
+            TriggerAction::ResumeAging => self.player.resume_aging(),
+            TriggerAction::MoveCamera(x, y) => self.triggers.camera_offset = (x, y),
+            TriggerAction::SetFlag(flag) => {
+                self.flags.insert(flag);
+            }
+            TriggerAction::Call(function) => self.run_script(&function),
+            TriggerAction::StartDialogue(_)
+            | TriggerAction::ChangeMusic(_)
+            | TriggerAction::PlaySound(_) => self.trigger_events.push(action),
+        }
+    }
+
+    // on_start once per visit to the level, on_reborn with every new life, on_update every step
+    fn run_script_hooks(&mut self) {
+        let starting = self
+            .script
+            .as_mut()
+            .is_some_and(|script| !mem::replace(&mut script.started, true));
+        if starting {
+            self.run_script("on_start");
+        }
+        let reborn = self
+            .player
+            .age_events
+            .iter()
+            .any(|event| matches!(event, AgeEvent::Reborn));
+        if reborn {
+            self.run_script("on_reborn");
+        }
+        self.run_script("on_update");
+    }
+
+    fn run_script(&mut self, function: &str) {
+        // Taken out while it runs so it can look at the rest of the simulation
+        let Some(mut script) = self.script.take() else {
+            return;
+        };
+        let commands = script.call(function, self);
+        self.script = Some(script);
+
+        for command in commands {
+            match command {
+                ScriptCommand::Action(action) => self.apply(action),
+                ScriptCommand::SetTile(pos, block_type) => self.change(pos, block_type),
+                ScriptCommand::ClearFlag(flag) => {
+                    self.flags.remove(&flag);
+                }
+            }
+        }
+    }
+
+    // The latest change to each tile is kept so a save can put the level back the way it was left
+    pub fn change(&mut self, pos: (usize, usize), block_type: BlockType) {
+        if self.map.in_bounds(pos) {
+            self.map.insert(pos, block_type);
+            self.changes.insert(pos, block_type);
+        }
+    }
+
+    // What a life does to the level stays for the lives after it
+    fn leave_marks(&mut self, was_dying: bool) {
+        let body = self.player.collision_box;
+
+        // Pulling any lever opens every gate
+        if self.player.touches(&self.map, BlockType::Lever) {
+            let levers: Vec<(usize, usize)> = self
+                .map
+                .blocks_in(&body, 0)
+                .filter(|(_, b)| *b == BlockType::Lever)
+                .map(|(pos, _)| pos)
+                .collect();
+            let gates: Vec<(usize, usize)> = self
+                .map
+                .iter()
+                .filter(|(_, b)| *b == BlockType::Gate)
+                .map(|(pos, _)| pos)
+                .collect();
+            for pos in levers {
+                self.change(pos, BlockType::LeverPulled);
+            }
+            for pos in gates {
+                self.change(pos, BlockType::Blank);
+            }
+        }
+
+        if self.player.grounded {
+            let feet = Rect {
+                x: body.x,
+                y: body.y + body.height,
+                width: body.width,
+                height: 1.0,
+            };
+            let cracked: Vec<(usize, usize)> = self
+                .map
+                .blocks_in(&feet, 0)
+                .filter(|(_, b)| *b == BlockType::Cracked)
+                .map(|(pos, _)| pos)
+                .collect();
+            for pos in cracked {
+                self.change(pos, BlockType::Blank);
+            }
+        }
+
+        if !was_dying && self.player.is_dying {
+            self.drop_grave();
+        }
+    }
+
+    fn drop_grave(&mut self) {
+        let body = self.player.collision_box;
+        let (x, y) = WorldMap::tile_at(body.x + body.width / 2.0, body.y + body.height - 1.0);
+        if x < 0 || y < 0 {
+            return;
+        }
+        let pos = (x as usize, y as usize);
+
+        // A grave on the spawn would be drawn over every new life
+        let (spawn_x, spawn_y) = self.player.spawn_position;
+        let spawn = Rect::new(spawn_x, spawn_y, SPRITE_SIZE, SPRITE_SIZE);
+
+        if self.map.get(pos) == Some(BlockType::Blank) && !Rect::tile(pos.0, pos.1).overlaps(&spawn)
+        {
+            self.change(pos, BlockType::Grave);
+        }
+    }
+
+    fn find_spawn_position(header: &MapHeader, map: &WorldMap) -> (f32, f32) {
+        match header.spawn.or_else(|| map.find(BlockType::Start)) {
+            Some((x, y)) => (
+                (x as f32) * BLOCK_SIZE as f32,
+                (y as f32) * BLOCK_SIZE as f32,
+            ),
+            None => (BASE_WIDTH as f32 / 2.0, BASE_HEIGHT as f32 / 2.0),
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::age::{Age, AgeStage};
+
+    const FLOOR_Y: f32 = 10.0 * BLOCK_SIZE as f32;
+
+    // An open room with a solid floor on row 10, starting on its left
+    pub(crate) fn room() -> WorldMap {
+        let mut map = WorldMap::new(20, 12);
+        for x in 0..20 {
+            for y in 0..10 {
+                map.insert((x, y), BlockType::Blank);
+            }
+            map.insert((x, 10), BlockType::StoneSlabUp);
+        }
+        map.insert((2, 9), BlockType::Start);
+        map
+    }
+
+    pub(crate) fn stage(name: &str, duration: f64) -> AgeStage {
+        AgeStage {
+            name: name.to_string(),
+            duration,
+            sight: 1.0,
+            strength: 1.0,
+            speed: 1.0,
+            jump_cooldown: 0.0,
+            collision_box_height: SPRITE_SIZE / 4. * 3.,
+            sprite_row: 0,
+        }
+    }
+
+    fn simulation(map: WorldMap, stages: Vec<AgeStage>) -> Simulation {
+        Simulation::new(
+            Campaign::single("test.json"),
+            vec![(MapHeader::default(), map)],
+            LifeCycle { stages },
+        )
+    }
+
+    // The first map starts a campaign that goes on to the second
+    pub(crate) fn two_levels(
+        first: WorldMap,
+        second: WorldMap,
+        stages: Vec<AgeStage>,
+    ) -> Simulation {
+        let mut campaign = Campaign::single("first.json");
+        campaign.levels.push(Level {
+            name: "Second".to_string(),
+            map: "second.json".to_string(),
+            ending: LevelEnding::Devil,
+        });
+        let levels = vec![
+            (MapHeader::default(), first),
+            (MapHeader::default(), second),
+        ];
+        Simulation::new(campaign, levels, LifeCycle { stages })
+    }
+
+    pub(crate) fn run(simulation: &mut Simulation, clock: &mut Clock, input: Input, steps: usize) {
+        for _ in 0..steps {
+            clock.tick(FIXED_TIMESTEP as f32);
+            simulation.update(&input, clock);
+        }
+    }
+
+    fn feet(simulation: &Simulation) -> f32 {
+        let body = simulation.player.collision_box;
+        body.y + body.height
+    }
+
+    const JUMP: Input = Input {
+        left: false,
+        right: false,
+        jump: true,
+        down: false,
+    };
+    const RIGHT: Input = Input {
+        left: false,
+        right: true,
+        jump: false,
+        down: false,
+    };
+    const DOWN: Input = Input {
+        left: false,
+        right: false,
+        jump: false,
+        down: true,
+    };
+
+    #[test]
+    fn jump_rises_by_the_arc_of_its_speed_and_lands_again() {
+        let mut simulation = simulation(room(), vec![stage("Adult", 100.0)]);
+        let mut clock = Clock::default();
+        run(&mut simulation, &mut clock, Input::default(), 30);
+        assert!(simulation.player.grounded);
+        assert_eq!(feet(&simulation), FLOOR_Y);
+
+        // Gravity is added before each move, so the first step already slows the jump
+        let expected: f32 = (1..)
+            .map(|step| JUMP_SPEED - GRAVITY * step as f32)
+            .take_while(|speed| *speed > 0.0)
+            .sum();
+
+        run(&mut simulation, &mut clock, JUMP, 1);
+        assert!(!simulation.player.grounded);
+        let mut highest = feet(&simulation);
+        for _ in 0..60 {
+            run(&mut simulation, &mut clock, Input::default(), 1);
+            highest = highest.min(feet(&simulation));
+        }
+
+        assert!((FLOOR_Y - highest - expected).abs() < 0.01);
+        assert!(simulation.player.grounded);
+        assert_eq!(feet(&simulation), FLOOR_Y);
+    }
+
+    #[test]
+    fn ages_through_each_stage_on_time_and_then_dies() {
+        let stages = vec![
+            stage("Young", 1.0),
+            stage("Old", 1.0),
+            stage("Ancient", 1.0),
+        ];
+        let mut simulation = simulation(room(), stages);
+        let mut clock = Clock::default();
+
+        let mut events = Vec::new();
+        while clock.time < 3.5 {
+            run(&mut simulation, &mut clock, Input::default(), 1);
+            for event in simulation.player.age_events.drain(..) {
+                events.push((clock.time, event));
+            }
+        }
+
+        assert_eq!(events.len(), 3);
+        for (i, (time, _)) in events.iter().enumerate() {
+            assert!((time - (i + 1) as f64).abs() <= 2.0 * FIXED_TIMESTEP);
+        }
+        assert!(matches!(events[0].1, AgeEvent::Aged(Age(1))));
+        assert!(matches!(events[1].1, AgeEvent::Aged(Age(2))));
+        assert!(matches!(events[2].1, AgeEvent::Died));
+        assert!(simulation.player.is_dying);
+    }
+
+    #[test]
+    fn death_leaves_a_grave_and_respawns_a_new_life_at_the_start() {
+        let mut simulation = simulation(room(), vec![stage("Adult", 2.0)]);
+        let mut clock = Clock::default();
+        let spawn = simulation.player.spawn_position;
+
+        run(&mut simulation, &mut clock, RIGHT, 30);
+        run(&mut simulation, &mut clock, Input::default(), 100);
+        assert!(simulation.player.is_dying);
+        let grave = simulation
+            .changes
+            .iter()
+            .find(|(_, block_type)| **block_type == BlockType::Grave)
+            .map(|(pos, _)| *pos)
+            .expect("no grave left");
+        assert_eq!(grave.1, 9);
+
+        let steps = (DEATH_ANIMATION_DURATION / FIXED_TIMESTEP) as usize + 2;
+        run(&mut simulation, &mut clock, Input::default(), steps);
+        let player = &simulation.player;
+        assert!(!player.is_dying);
+        assert_eq!(player.lives, 2);
+        assert_eq!(player.age, Age(0));
+        assert_eq!((player.body.x, player.body.y), spawn);
+        assert_eq!(simulation.map.get(grave), Some(BlockType::Grave));
+
+        // Graves are markers, the next life walks straight past
+        run(&mut simulation, &mut clock, RIGHT, 60);
+        let grave_right = ((grave.0 + 1) * BLOCK_SIZE as usize) as f32;
+        assert!(simulation.player.collision_box.x > grave_right);
+    }
+
+    #[test]
+    fn one_way_platform_is_jumped_through_landed_on_and_dropped_through() {
+        let mut map = room();
+        for x in 0..20 {
+            map.insert((x, 8), BlockType::Slab);
+        }
+        let platform_y = 8.0 * BLOCK_SIZE as f32;
+        let mut simulation = simulation(map, vec![stage("Adult", 100.0)]);
+        let mut clock = Clock::default();
+        run(&mut simulation, &mut clock, Input::default(), 30);
+        assert_eq!(feet(&simulation), FLOOR_Y);
+
+        run(&mut simulation, &mut clock, JUMP, 1);
+        run(&mut simulation, &mut clock, Input::default(), 60);
+        assert!(simulation.player.grounded);
+        assert_eq!(feet(&simulation), platform_y);
+
+        run(&mut simulation, &mut clock, DOWN, 30);
+        assert!(simulation.player.grounded);
+        assert_eq!(feet(&simulation), FLOOR_Y);
+    }
+
+    #[test]
+    fn next_level_ages_again_after_aging_stopped_on_the_last_one() {
+        let mut first = room();
+        first.add_trigger(Trigger::between((2, 9), (2, 9), TriggerAction::StopAging));
+        first.insert((6, 9), BlockType::Exit);
+        let stages = vec![stage("Young", 1.0), stage("Old", 100.0)];
+        let mut simulation = two_levels(first, room(), stages);
+        let mut clock = Clock::default();
+
+        run(&mut simulation, &mut clock, Input::default(), 5);
+        assert!(!simulation.player.can_age);
+        run(&mut simulation, &mut clock, RIGHT, 60);
+        assert_eq!(simulation.level, 1);
+        assert!(simulation.player.can_age);
+        assert!(!simulation.player.end_scene_active);
+
+        run(&mut simulation, &mut clock, Input::default(), 90);
+        assert_eq!(simulation.player.age, Age(1));
+    }
+
+    #[test]
+    fn script_vars_come_back_with_the_level_state() {
+        let source = r#"
+            fn on_update() {
+                let steps = get_var("steps");
+                set_var("steps", if steps == () { 1 } else { steps + 1 });
+            }
+        "#;
+        let mut first = simulation(room(), vec![stage("Adult", 100.0)]);
+        first.set_scripts(vec![Some(source.to_string())]);
+        run(&mut first, &mut Clock::default(), Input::default(), 10);
+
+        let json = serde_json::to_string(&first.level_state()).unwrap();
+        let state: LevelState = serde_json::from_str(&json).unwrap();
+        let mut second = simulation(room(), vec![stage("Adult", 100.0)]);
+        second.set_scripts(vec![Some(source.to_string())]);
+        second.restore_level_state(&state);
+        run(&mut second, &mut Clock::default(), Input::default(), 5);
+
+        let steps = second.level_state().script_vars["steps"].as_int();
+        assert_eq!(steps, Ok(15));
+    }
+
+    #[test]
+    fn boulder_half_over_a_ledge_rolls_off_and_lands_below() {
+        let mut map = room();
+        for x in 0..5 {
+            map.insert((x, 6), BlockType::StoneSlabUp);
+        }
+        let ledge_edge = 5.0 * BLOCK_SIZE as f32;
+        let mut simulation = simulation(map, vec![stage("Adult", 100.0)]);
+        let ledge_top = 6.0 * BLOCK_SIZE as f32 - BOULDER_SIZE;
+        simulation.entities = vec![Entity::new(
+            EntityKind::Boulder,
+            ledge_edge - 3.0,
+            ledge_top,
+        )];
+
+        run(
+            &mut simulation,
+            &mut Clock::default(),
+            Input::default(),
+            120,
+        );
+        let boulder = &simulation.entities[0];
+        assert!(boulder.body.x >= ledge_edge);
+        assert!(boulder.grounded);
+        assert_eq!(boulder.body.y + boulder.body.height, FLOOR_Y);
+    }
+
+    #[test]
+    fn boulder_lands_on_another_instead_of_falling_through() {
+        let mut map = room();
+        map.add_entity(EntitySpawn {
+            kind: EntityKind::Boulder,
+            x: 8,
+            y: 9,
+        });
+        map.add_entity(EntitySpawn {
+            kind: EntityKind::Boulder,
+            x: 8,
+            y: 3,
+        });
+        let mut simulation = simulation(map, vec![stage("Adult", 100.0)]);
+
+        run(
+            &mut simulation,
+            &mut Clock::default(),
+            Input::default(),
+            120,
+        );
+        let (below, above) = (&simulation.entities[0], &simulation.entities[1]);
+        assert_eq!(below.body.y + below.body.height, FLOOR_Y);
+        assert!(above.grounded);
+        assert_eq!(above.body.y + above.body.height, below.body.y);
+    }
+}
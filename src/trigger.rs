@@ -0,0 +1,197 @@
+use crate::*;
+use serde::{Deserialize, Serialize};
+
+// What happens when the player walks into a trigger
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum TriggerAction {
+    StartDialogue(String), // Node of dialogue.json to play over the level
+    EndScene,              // The devil's lead in, then the ending dialogue
+    StopAging,
+    ResumeAging,
+    ChangeMusic(String),  // Path of the track to switch to
+    PlaySound(String),    // Named like the dialogue's sound effects
+    MoveCamera(f32, f32), // Offset from the player in tiles, for the rest of the life
+    SetFlag(String),
+    Call(String), // Function of the level's script
+}
+
+impl TriggerAction {
+    // One of each, in the order the builder cycles through them
+    pub fn presets() -> [TriggerAction; 9] {
+        [
+            Self::StopAging,
+            Self::ResumeAging,
+            Self::EndScene,
+            Self::StartDialogue("arrival".to_string()),
+            Self::ChangeMusic(MUSIC_PATH.to_string()),
+            Self::PlaySound("laugh".to_string()),
+            Self::MoveCamera(0.0, -4.0),
+            Self::SetFlag("trigger".to_string()),
+            Self::Call("on_trigger".to_string()),
+        ]
+    }
+
+    // Text the builder lets the designer type in, for actions that take any
+    pub fn value(&self) -> Option<String> {
+        match self {
+            Self::StartDialogue(value)
+            | Self::ChangeMusic(value)
+            | Self::PlaySound(value)
+            | Self::SetFlag(value)
+            | Self::Call(value) => Some(value.clone()),
+            Self::MoveCamera(x, y) => Some(format!("{} {}", x, y)),
+            _ => None,
+        }
+    }
+
+    // Keeps the old value when the new one cannot be read
+    pub fn set_value(&mut self, input: &str) {
+        match self {
+            Self::StartDialogue(value)
+            | Self::ChangeMusic(value)
+            | Self::PlaySound(value)
+            | Self::SetFlag(value)
+            | Self::Call(value) => {
+                *value = input.trim().to_string();
+            }
+            Self::MoveCamera(x, y) => {
+                let numbers: Vec<f32> = input
+                    .split_whitespace()
+                    .filter_map(|n| n.parse().ok())
+                    .collect();
+                if let [new_x, new_y] = numbers[..] {
+                    (*x, *y) = (new_x, new_y);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TriggerMode {
+    Once,   // Only the first time in a level
+    Repeat, // Every time the player walks in
+}
+
+// A rectangle of tiles, kept in the map file next to the entities
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Trigger {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+    pub action: TriggerAction,
+    pub mode: TriggerMode,
+}
+
+impl Trigger {
+    // Spans the two corner tiles, in any order
+    pub fn between(
+        (x1, y1): (usize, usize),
+        (x2, y2): (usize, usize),
+        action: TriggerAction,
+    ) -> Self {
+        Self {
+            x: x1.min(x2),
+            y: y1.min(y2),
+            width: x1.abs_diff(x2) + 1,
+            height: y1.abs_diff(y2) + 1,
+            action,
+            mode: TriggerMode::Once,
+        }
+    }
+
+    pub fn contains(&self, (x, y): (usize, usize)) -> bool {
+        (self.x..self.x + self.width).contains(&x) && (self.y..self.y + self.height).contains(&y)
+    }
+
+    pub fn area(&self) -> Rect {
+        Rect {
+            x: (self.x as f32) * BLOCK_SIZE as f32,
+            y: (self.y as f32) * BLOCK_SIZE as f32,
+            width: (self.width as f32) * BLOCK_SIZE as f32,
+            height: (self.height as f32) * BLOCK_SIZE as f32,
+        }
+    }
+}
+
+// Which triggers the player is in and which have gone off, for the current level
+#[derive(Clone, Debug, Default)]
+pub struct TriggerState {
+    pub fired: Vec<bool>, // Once triggers that already went off
+    inside: Vec<bool>,
+    life: u32,                     // Each life walks into the triggers afresh
+    pub camera_offset: (f32, f32), // Left by the last MoveCamera, in tiles
+}
+
+impl TriggerState {
+    pub fn new(count: usize) -> Self {
+        Self {
+            fired: vec![false; count],
+            inside: vec![false; count],
+            life: 0,
+            camera_offset: (0.0, 0.0),
+        }
+    }
+
+    // Indices of the triggers walked into this step
+    pub fn entered(&mut self, triggers: &[Trigger], player: &Player) -> Vec<usize> {
+        if player.lives != self.life {
+            self.life = player.lives;
+            self.inside.fill(false);
+            self.camera_offset = (0.0, 0.0);
+        }
+
+        let mut entered = Vec::new();
+        for (i, trigger) in triggers.iter().enumerate() {
+            let inside = !player.is_dying && trigger.area().overlaps(&player.collision_box);
+            if inside && !self.inside[i] && !self.fired[i] {
+                entered.push(i);
+                self.fired[i] = trigger.mode == TriggerMode::Once;
+            }
+            self.inside[i] = inside;
+        }
+        entered
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::age::LifeCycle;
+
+    // The player walks in and out of a trigger on the tiles (4, 4) to (5, 5)
+    fn walk(mode: TriggerMode) -> Vec<Vec<usize>> {
+        let trigger = Trigger {
+            mode,
+            ..Trigger::between((4, 4), (5, 5), TriggerAction::StopAging)
+        };
+        let triggers = [trigger];
+        let mut state = TriggerState::new(triggers.len());
+        let mut player = Player::new(0.0, 4.0 * BLOCK_SIZE as f32, LifeCycle::default());
+
+        let inside = 4.0 * BLOCK_SIZE as f32;
+        [inside, 0.0, 0.0, inside, inside, 0.0, inside]
+            .into_iter()
+            .map(|x| {
+                player.shift(x - player.body.x, 0.0);
+                state.entered(&triggers, &player)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn once_trigger_fires_a_single_time() {
+        let fired = walk(TriggerMode::Once);
+        assert_eq!(fired[0], vec![0]);
+        assert!(fired[1..].iter().all(|entered| entered.is_empty()));
+    }
+
+    #[test]
+    fn repeat_trigger_fires_again_only_after_leaving() {
+        let fired = walk(TriggerMode::Repeat);
+        let expected: [&[usize]; 7] = [&[0], &[], &[], &[0], &[], &[], &[0]];
+        assert_eq!(fired, expected);
+    }
+}
@@ -1,31 +1,31 @@
-use crate::{dust::Dust, *};
-use raylib::prelude::*;
+use crate::player::AgeEvent;
+use crate::{dust::Dust, ghost::Ghosts, *};
 use std::error::Error;
 
 pub struct World {
-    pub map: WorldMap,
-    pub player: Player,
+    pub simulation: Simulation,
     pub camera: Camera2D,
+    pub previous_camera_target: Vector2,
+    pub player_texture: Texture2D,
     pub tileset_texture: Texture2D,
     pub devil_texture: Texture2D,
     pub bg_texture: Texture2D,
     pub dust: Dust,
-    pub camera_offset_y: f32,
-    pub target_camera_offset_y: f32,
+    pub ghosts: Ghosts,
+    pub camera_offset: Vector2,
+    pub target_camera_offset: Vector2,
 }
 
 impl World {
     pub fn new(
         game_handle: &mut RaylibHandle,
         game_thread: &RaylibThread,
+        simulation: Simulation,
     ) -> Result<Self, Box<dyn Error>> {
-        let map = load_map();
-        let spawn_pos = Self::find_spawn_position(&map);
-        let player = Player::new(game_handle, game_thread, spawn_pos.0, spawn_pos.1)?;
+        let spawn_pos = simulation.player.spawn_position;
 
         Ok(Self {
-            map,
-            player,
+            simulation,
             camera: Camera2D {
                 offset: Vector2 {
                     x: BASE_WIDTH as f32 / 2.0,
@@ -38,46 +38,98 @@ impl World {
                 rotation: 0.0,
                 zoom: CAMERA_ZOOM,
             },
+            previous_camera_target: Vector2 {
+                x: spawn_pos.0 + SPRITE_SIZE,
+                y: spawn_pos.1 + SPRITE_SIZE,
+            },
+            player_texture: game_handle.load_texture(game_thread, PLAYER_SPRITE_PATH)?,
             tileset_texture: game_handle.load_texture(game_thread, TILESET_PATH)?,
             bg_texture: game_handle.load_texture(game_thread, BG_PATH)?,
             devil_texture: game_handle.load_texture(game_thread, DEVIL_PATH)?,
             dust: Dust::new(game_handle, game_thread)?,
-            camera_offset_y: 0.0,
-            target_camera_offset_y: 0.0,
+            ghosts: Ghosts::default(),
+            camera_offset: Vector2::zero(),
+            target_camera_offset: Vector2::zero(),
         })
     }
 
-    fn find_spawn_position(map: &WorldMap) -> (f32, f32) {
-        for ((x, y), block_type) in map {
-            if *block_type == BlockType::Start {
-                return (
-                    (*x as f32) * BLOCK_SIZE as f32,
-                    (*y as f32) * BLOCK_SIZE as f32,
-                );
-            }
+    pub fn update(&mut self, input: &Input, clock: &Clock) -> bool {
+        let level = self.simulation.level;
+        let footstep = self.simulation.update(input, clock);
+
+        if self.simulation.level != level {
+            self.snap_camera();
         }
-        (BASE_WIDTH as f32 / 2.0, BASE_HEIGHT as f32 / 2.0)
+
+        // Events are still pending here, also those of a respawn from outside the step
+        let player = &self.simulation.player;
+        if player
+            .age_events
+            .iter()
+            .any(|event| matches!(event, AgeEvent::Reborn))
+        {
+            self.ghosts.end_life();
+        }
+        self.ghosts.record(player, self.simulation.level);
+
+        footstep
     }
 
-    pub fn draw<D: RaylibDraw>(&mut self, d: &mut D, _width: &i32, _height: &i32, _time: &f64) {
-        let mut d = d.begin_mode2D(self.camera);
+    // Jumps to the spawn instead of panning across a level change
+    pub fn snap_camera(&mut self) {
+        let (x, y) = self.simulation.player.spawn_position;
+        self.camera.target = Vector2 {
+            x: x + SPRITE_SIZE,
+            y: y + SPRITE_SIZE,
+        };
+        self.previous_camera_target = self.camera.target;
+    }
+
+    // Starts a new run without reloading the textures
+    pub fn reset(&mut self, simulation: Simulation) {
+        self.simulation = simulation;
+        self.ghosts.clear();
+        self.snap_camera();
+        self.camera_offset = Vector2::zero();
+        self.target_camera_offset = Vector2::zero();
+    }
+
+    pub fn draw<D: RaylibDraw>(&mut self, d: &mut D, _width: &i32, _height: &i32, alpha: f32) {
+        let camera = self.interpolated_camera(alpha);
+        let mut d = d.begin_mode2D(camera);
         d.clear_background(BG_COLOR);
 
-        self.draw_background(&mut d);
-        self.draw_blocks(&mut d);
-        self.player.draw(&mut d);
+        self.draw_background(&mut d, &camera);
+        self.draw_blocks(&mut d, &camera);
+        self.ghosts.draw(&mut d, &self.player_texture, alpha);
+        for entity in &self.simulation.entities {
+            self.draw_entity(&mut d, entity, alpha);
+        }
+        self.simulation
+            .player
+            .draw(&mut d, &self.player_texture, alpha);
         self.dust.draw(&mut d);
     }
 
-    fn draw_background<D: RaylibDraw>(&self, d: &mut D) {
+    fn interpolated_camera(&self, alpha: f32) -> Camera2D {
+        Camera2D {
+            target: Vector2 {
+                x: smoothing(self.previous_camera_target.x, self.camera.target.x, alpha),
+                y: smoothing(self.previous_camera_target.y, self.camera.target.y, alpha),
+            },
+            ..self.camera
+        }
+    }
+
+    fn draw_background<D: RaylibDraw>(&self, d: &mut D, camera: &Camera2D) {
         let bg_width = self.bg_texture.width() as f32;
         let bg_height = self.bg_texture.height() as f32;
 
         d.draw_texture_ex(
             &self.bg_texture,
             Vector2 {
-                x: self.camera.target.x - bg_width / 2.0,
-                y: self.camera.target.y - bg_height / 2.0,
+                x: camera.target.x - bg_width / 2.0,
+                y: camera.target.y - bg_height / 2.0,
             },
             0.0,
             1.0,
@@ -85,45 +137,135 @@ impl World {
         );
     }
 
-    fn draw_blocks<D: RaylibDraw>(&self, d: &mut D) {
-        for ((x, y), block_type) in &self.map {
-            if matches!(
-                block_type,
-                BlockType::Start | BlockType::Blank | BlockType::StopAging
-            ) {
+    fn draw_blocks<D: RaylibDraw>(&self, d: &mut D, camera: &Camera2D) {
+        let view = visible_area(camera);
+        for ((x, y), block_type) in self.simulation.map.blocks_in(&view, 0) {
+            if matches!(block_type, BlockType::Start | BlockType::Blank) {
                 continue;
             }
 
-            let block_x = (*x as i32) * BLOCK_SIZE;
-            let block_y = (*y as i32) * BLOCK_SIZE;
+            let block_x = (x as i32) * BLOCK_SIZE;
+            let block_y = (y as i32) * BLOCK_SIZE;
 
-            if *block_type == BlockType::End {
-                self.draw_devil(d, block_x as f32, block_y as f32);
-            } else {
-                self.draw_tile(d, block_type, block_x as f32, block_y as f32);
+            match block_type {
+                BlockType::Exit => d.draw_rectangle(
+                    block_x,
+                    block_y,
+                    BLOCK_SIZE,
+                    BLOCK_SIZE,
+                    Color::new(235, 219, 178, 40),
+                ),
+                BlockType::Lever | BlockType::LeverPulled => {
+                    // The handle leans the other way once pulled
+                    let knob_x = if block_type == BlockType::Lever { 1 } else { 5 };
+                    d.draw_rectangle(
+                        block_x + 3,
+                        block_y + 4,
+                        2,
+                        4,
+                        Color::new(124, 111, 100, 255),
+                    );
+                    d.draw_rectangle(
+                        block_x + knob_x,
+                        block_y + 2,
+                        2,
+                        2,
+                        Color::new(251, 73, 52, 255),
+                    );
+                }
+                BlockType::Grave => d.draw_rectangle(
+                    block_x + 2,
+                    block_y + 2,
+                    BLOCK_SIZE - 4,
+                    BLOCK_SIZE - 2,
+                    Color::new(168, 153, 132, 255),
+                ),
+                BlockType::Gate => self.draw_tile(
+                    d,
+                    &block_type,
+                    block_x as f32,
+                    block_y as f32,
+                    Color::new(131, 165, 152, 255),
+                ),
+                BlockType::Slab => self.draw_platform(d, block_x as f32, block_y as f32),
+                BlockType::Cracked => self.draw_tile(
+                    d,
+                    &block_type,
+                    block_x as f32,
+                    block_y as f32,
+                    Color::new(146, 131, 116, 255),
+                ),
+                _ => self.draw_tile(d, &block_type, block_x as f32, block_y as f32, Color::WHITE),
             }
         }
     }
 
-    fn draw_devil<D: RaylibDraw>(&self, d: &mut D, x: f32, y: f32) {
+    fn draw_entity<D: RaylibDraw>(&self, d: &mut D, entity: &Entity, alpha: f32) {
+        let position = Vector2 {
+            x: smoothing(entity.previous_position.0, entity.body.x, alpha),
+            y: smoothing(entity.previous_position.1, entity.body.y, alpha),
+        };
+
+        match entity.kind {
+            EntityKind::Devil => d.draw_texture_rec(
+                &self.devil_texture,
+                Rectangle {
+                    x: 0.0,
+                    y: 0.0,
+                    width: -SPRITE_SIZE,
+                    height: DEVIL_HEIGHT,
+                },
+                position,
+                Color::WHITE,
+            ),
+            EntityKind::Boulder => {
+                let radius = entity.body.width / 2.0;
+                let center = Vector2 {
+                    x: position.x + radius,
+                    y: position.y + radius,
+                };
+
+                d.draw_circle_v(center, radius, Color::new(124, 111, 100, 255));
+                // A notch that turns with the boulder so rolling shows
+                d.draw_line_ex(
+                    center,
+                    Vector2 {
+                        x: center.x + entity.angle.cos() * radius,
+                        y: center.y + entity.angle.sin() * radius,
+                    },
+                    1.0,
+                    Color::new(80, 73, 69, 255),
+                );
+            }
+        }
+    }
+
+    fn draw_tile<D: RaylibDraw>(
+        &self,
+        d: &mut D,
+        block_type: &BlockType,
+        x: f32,
+        y: f32,
+        tint: Color,
+    ) {
+        let (sprite_x, sprite_y) = block_type.to_sprite_position();
+
         d.draw_texture_rec(
-            &self.devil_texture,
+            &self.tileset_texture,
             Rectangle {
-                x: 0.0,
-                y: 0.0,
-                width: -SPRITE_SIZE,
-                height: DEVIL_HEIGHT,
-            },
-            Vector2 {
-                x,
-                y: y - DEVIL_HEIGHT + SPRITE_SIZE,
+                x: sprite_x * SPRITE_SIZE,
+                y: sprite_y * SPRITE_SIZE,
+                width: SPRITE_SIZE,
+                height: SPRITE_SIZE,
             },
-            Color::WHITE,
+            Vector2 { x, y },
+            tint,
         );
     }
 
-    fn draw_tile<D: RaylibDraw>(&self, d: &mut D, block_type: &BlockType, x: f32, y: f32) {
-        let (sprite_x, sprite_y) = block_type.to_sprite_position();
+    // Only the top of the tile, so it reads as something to jump through
+    fn draw_platform<D: RaylibDraw>(&self, d: &mut D, x: f32, y: f32) {
+        let (sprite_x, sprite_y) = BlockType::Slab.to_sprite_position();
 
         d.draw_texture_rec(
             &self.tileset_texture,
@@ -131,7 +273,7 @@ impl World {
                 x: sprite_x * SPRITE_SIZE,
                 y: sprite_y * SPRITE_SIZE,
                 width: SPRITE_SIZE,
-                height: SPRITE_SIZE,
+                height: SPRITE_SIZE / 4.0,
             },
             Vector2 { x, y },
             Color::WHITE,
@@ -143,37 +285,68 @@ impl World {
         self.update_camera_target();
     }
 
+    // Triggers can move the camera off the player, the end scene always looks up
     fn update_camera_offset(&mut self) {
-        self.target_camera_offset_y = if self.player.end_triggered {
-            END_SCENE_CAMERA_OFFSET_Y
-        } else {
-            0.0
+        let (x, y) = self.simulation.triggers.camera_offset;
+        self.target_camera_offset = Vector2 {
+            x: x * BLOCK_SIZE as f32,
+            y: if self.simulation.player.end_triggered {
+                END_SCENE_CAMERA_OFFSET_Y
+            } else {
+                y * BLOCK_SIZE as f32
+            },
         };
 
-        let diff = self.target_camera_offset_y - self.camera_offset_y;
-
-        if diff.abs() > 0.05 {
-            let t = (diff.abs() / END_SCENE_CAMERA_OFFSET_Y.abs()).clamp(0.0, 1.0);
-            let smoothstep = t * t * (3.0 - 2.0 * t);
-            self.camera_offset_y +=
-                diff * (END_SCENE_CAMERA_TRANSITION_SPEED * 2.0 + smoothstep * 0.05);
-        } else {
-            self.camera_offset_y = self.target_camera_offset_y;
-        }
+        self.camera_offset.x = ease_offset(self.camera_offset.x, self.target_camera_offset.x);
+        self.camera_offset.y = ease_offset(self.camera_offset.y, self.target_camera_offset.y);
     }
 
     fn update_camera_target(&mut self) {
+        self.previous_camera_target = self.camera.target;
         self.camera.target = Vector2 {
             x: smoothing(
                 self.camera.target.x,
-                self.player.body.x + SPRITE_SIZE,
+                self.simulation.player.body.x + SPRITE_SIZE + self.camera_offset.x,
                 CAMERA_SPEED,
             ),
             y: smoothing(
                 self.camera.target.y,
-                self.player.body.y + SPRITE_SIZE + self.camera_offset_y,
+                self.simulation.player.body.y + SPRITE_SIZE + self.camera_offset.y,
                 CAMERA_SPEED,
             ),
         };
     }
 }
+
+// Drawn here with the rest of the level, the player itself only knows its rectangles
+impl Player {
+    pub fn draw<D: RaylibDraw>(&self, d: &mut D, sprite: &Texture2D, alpha: f32) {
+        //d.draw_rectangle_rec(self.collision_box, Color::PURPLE);
+
+        d.draw_texture_pro(
+            sprite,
+            Rectangle::from(self.sprite_source()),
+            Rectangle {
+                x: smoothing(self.previous_position.0, self.body.x, alpha),
+                y: smoothing(self.previous_position.1, self.body.y, alpha),
+                width: PLAYER_SCALE * SPRITE_SIZE,
+                height: PLAYER_SCALE * SPRITE_SIZE,
+            },
+            Vector2::zero(),
+            0.0,
+            Color::WHITE,
+        );
+    }
+}
+
+fn ease_offset(current: f32, target: f32) -> f32 {
+    let diff = target - current;
+
+    if diff.abs() > 0.05 {
+        let t = (diff.abs() / END_SCENE_CAMERA_OFFSET_Y.abs()).clamp(0.0, 1.0);
+        let smoothstep = t * t * (3.0 - 2.0 * t);
+        current + diff * (END_SCENE_CAMERA_TRANSITION_SPEED * 2.0 + smoothstep * 0.05)
+    } else {
+        target
+    }
+}